@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use crate::helpers::{error::{BatchError, BatchErrorType}, file::Metadata};
+
+// columns dvs_status already returns, so annotations can't be named after them
+const RESERVED_NAMES: [&str; 12] = [
+    "relative_path", "status", "size", "add_time", "saved_by", "message", "absolute_path",
+    "blake3_checksum", "tags", "input", "error", "error_message"
+];
+
+// changes to the tags and annotations of tracked files, from dvs_add or dvs_annotate
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct AnnotationEdit {
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    pub set: BTreeMap<String, String>,
+    pub remove: Vec<String>,
+}
+
+impl AnnotationEdit {
+    pub fn is_empty(&self) -> bool {
+        self.add_tags.is_empty() && self.remove_tags.is_empty() && self.set.is_empty() && self.remove.is_empty()
+    }
+
+    pub fn check(&self) -> std::result::Result<(), BatchError> {
+        for tag in self.add_tags.iter().chain(&self.remove_tags) {
+            check_tag(tag)?;
+        }
+        for name in self.set.keys().chain(&self.remove) {
+            check_name(name)?;
+        }
+        Ok(())
+    }
+
+    // removals first, so a tag or annotation can be replaced in one edit; true if the metadata changed
+    pub fn apply(&self, metadata: &mut Metadata) -> bool {
+        let before = (metadata.tags.clone(), metadata.annotations.clone());
+
+        metadata.tags.retain(|tag| !self.remove_tags.contains(tag));
+        for tag in &self.add_tags {
+            if !metadata.tags.contains(tag) {
+                metadata.tags.push(tag.clone());
+            }
+        }
+
+        for name in &self.remove {
+            metadata.annotations.remove(name);
+        }
+        metadata.annotations.extend(self.set.clone());
+
+        before != (metadata.tags.clone(), metadata.annotations.clone())
+    }
+}
+
+// which files dvs_status reports: those with all of the tags and annotation values given
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct AnnotationFilter {
+    pub tags: Vec<String>,
+    pub annotations: BTreeMap<String, String>,
+}
+
+impl AnnotationFilter {
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty() && self.annotations.is_empty()
+    }
+
+    pub fn matches(&self, metadata: &Metadata) -> bool {
+        self.tags.iter().all(|tag| metadata.tags.contains(tag)) &&
+            self.annotations.iter().all(|(name, value)| metadata.annotations.get(name) == Some(value))
+    }
+}
+
+// tags are returned joined with ", " in one column
+fn check_tag(tag: &String) -> std::result::Result<(), BatchError> {
+    if tag.trim().is_empty() || tag.trim() != tag || tag.contains(',') {
+        return Err(BatchError{
+            error: BatchErrorType::AnnotationInvalid,
+            error_message: format!("tag \"{tag}\" invalid: tags can't be empty, contain commas, or start or end with spaces")
+        })
+    }
+    Ok(())
+}
+
+// annotations are returned as columns, so their names must make good column names
+fn check_name(name: &String) -> std::result::Result<(), BatchError> {
+    let mut chars = name.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic()) &&
+        chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.');
+    if !valid {
+        return Err(BatchError{
+            error: BatchErrorType::AnnotationInvalid,
+            error_message: format!("annotation name \"{name}\" invalid: names start with a letter and contain only letters, digits, _ and .")
+        })
+    }
+    if RESERVED_NAMES.contains(&name.as_str()) {
+        return Err(BatchError{
+            error: BatchErrorType::AnnotationInvalid,
+            error_message: format!("annotation name \"{name}\" invalid: it's the name of a column dvs_status returns")
+        })
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use rayon::prelude::*;
+use rayon::ThreadPool;
+use std::{collections::HashMap, path::{Path, PathBuf}, sync::{Arc, Mutex, OnceLock}};
+use crate::helpers::{error::{BatchError, BatchErrorType, FileError, FileErrorType}, file, progress::{self, Progress}};
+
+// pools started so far, by number of threads (0 for one per core); starting threads for every call
+// adds up when a script adds or gets files one at a time
+static POOLS: OnceLock<Mutex<HashMap<usize, Arc<ThreadPool>>>> = OnceLock::new();
+
+// the pool with the given number of threads, or one per core, started the first time it's needed
+fn pool(workers: Option<usize>) -> std::result::Result<Arc<ThreadPool>, BatchError> {
+    let num_threads = workers.unwrap_or(0);
+    let mut pools = POOLS.get_or_init(|| Mutex::new(HashMap::new())).lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(pool) = pools.get(&num_threads) {
+        return Ok(pool.clone())
+    }
+    let pool = Arc::new(rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()
+        .map_err(|e|
+            BatchError{
+                error: BatchErrorType::WorkersInvalid,
+                error_message: format!("could not start {num_threads} workers: {e}")
+            }
+        )?);
+    pools.insert(num_threads, pool.clone());
+    Ok(pool)
+}
+
+// runs per-file work on the given number of threads, or one per core;
+// results are in the same order as the inputs, and work f does in parallel, see map_within, runs on the
+// same threads. with progress, files not started once the batch is cancelled, and files whose work
+// was cut short, fail as cancelled
+pub fn map_files<I, T, F>(inputs: &[I], workers: Option<usize>, progress: Option<&Arc<Progress>>, f: F) -> std::result::Result<Vec<std::result::Result<T, FileError>>, BatchError>
+where
+    I: AsRef<Path> + Sync,
+    T: Send,
+    F: Fn(&I) -> std::result::Result<T, FileError> + Send + Sync,
+{
+    let pool = pool(workers)?;
+
+    let Some(progress) = progress else {
+        return Ok(pool.install(|| inputs.par_iter().map(|input| f(input)).collect()))
+    };
+
+    progress.start_batch(inputs.len());
+    Ok(pool.install(|| inputs.par_iter().enumerate().map(|(index, input)| {
+        let path = input.as_ref();
+        if progress.is_cancelled() {
+            return Err(cancelled_error(path, String::from("cancelled before it was started")))
+        }
+        progress::for_file(progress, index, path, || f(input)).map_err(|e|
+            if progress.is_cancelled() {
+                cancelled_error(path, String::from("cancelled before it finished; it was left as it was"))
+            }
+            else {
+                e
+            }
+        )
+    }).collect()))
+}
+
+fn cancelled_error(path: &Path, message: String) -> FileError {
+    let path = PathBuf::from(path);
+    FileError{
+        relative_path: file::try_to_get_rel_path(&path),
+        absolute_path: file::try_to_get_abs_path(&path),
+        error: FileErrorType::Cancelled,
+        error_message: Some(message),
+        input: path,
+    }
+}
+
+// run f on each input in parallel on the threads of the batch it's called from, as part of the file the
+// calling worker is on, e.g. for the files of a directory; results are in the same order as the inputs
+pub fn map_within<I, T, F>(inputs: &[I], f: F) -> Vec<T>
+where
+    I: Sync,
+    T: Send,
+    F: Fn(&I) -> T + Send + Sync,
+{
+    let scope = progress::file_scope();
+    inputs.par_iter().map(|input| progress::in_scope(&scope, || f(input))).collect()
+}
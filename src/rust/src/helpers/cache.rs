@@ -1,6 +1,6 @@
-use std::{fs::{self, File}, path::PathBuf, time::SystemTime};
+use std::{fs, path::PathBuf, time::SystemTime};
 use serde::{Deserialize, Serialize};
-use crate::helpers::repo;
+use crate::helpers::{copy, repo};
 use xdg;
 
 
@@ -14,8 +14,8 @@ pub struct CacheData {
 }
 
 pub fn get_cached_hash(path: &PathBuf) -> Result<String> {
-    // get absolute path
-    let abs_path = path.canonicalize()?;
+    // get absolute path; not canonicalized, so a file symlinked into storage is cached under its own path
+    let abs_path = repo::absolutize_result(path)?;
     
     // open the cache
     let cache_path = get_cache_path(&abs_path)?;
@@ -27,7 +27,8 @@ pub fn get_cached_hash(path: &PathBuf) -> Result<String> {
     let current_modification_time = Some(abs_path.metadata()?.modified()?);
 
     if current_modification_time != cache_data.modification_time {
-        fs::remove_file(cache_path)?;
+        // another process may have removed or replaced it already
+        let _ = fs::remove_file(cache_path);
         // "file modification time does not match cache (invalidating)"
         return Err("file modification time does not match cache (invalidating)".into());
     }
@@ -36,7 +37,7 @@ pub fn get_cached_hash(path: &PathBuf) -> Result<String> {
 }
 
 pub fn write_hash_to_cache(path: &PathBuf, hash: &String) -> Result<()> {
-    let abs_path = path.canonicalize()?;
+    let abs_path = repo::absolutize_result(path)?;
 
     // cache_path = $HOME/.cache/dvs/<project_name>/<relative path between file and git directory>
     let cache_path = get_cache_path(&abs_path)?;
@@ -47,9 +48,6 @@ pub fn write_hash_to_cache(path: &PathBuf, hash: &String) -> Result<()> {
     )?;
     fs::create_dir_all(&parent_path)?;
 
-    // create file
-    File::create(&cache_path)?;
-
     let modification_time = Some(abs_path.metadata()?.modified()?);
 
     // put modification_time and hash into struct
@@ -61,10 +59,14 @@ pub fn write_hash_to_cache(path: &PathBuf, hash: &String) -> Result<()> {
     // serialize file contents
     let contents = serde_json::to_string_pretty(&cached_data)?;
 
-    // write contents to file
-    fs::write(&cache_path, contents)?;
-
-    Ok(())
+    // write contents to a temporary file and move it into place, so a cache entry written by
+    // another thread or process at the same time is replaced whole rather than interleaved
+    let temp_path = copy::temp_path(&cache_path);
+    let result = fs::write(&temp_path, contents).and_then(|_| fs::rename(&temp_path, &cache_path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    Ok(result?)
 }
 
 fn get_cache_path(abs_path: &PathBuf) -> Result<PathBuf> {
@@ -73,9 +75,9 @@ fn get_cache_path(abs_path: &PathBuf) -> Result<PathBuf> {
     
     let project_name = PathBuf::from(git_dir
         .file_name()
-        .ok_or_else(|| format!("project name not found"))?
+        .ok_or_else(|| String::from("project name not found"))?
         .to_str()
-        .ok_or_else(|| format!("project name not found"))?
+        .ok_or_else(|| String::from("project name not found"))?
     );
 
     // partial_cache_path = project_name/<relative path between file and git directory>
@@ -0,0 +1,178 @@
+use std::{fs::{self, File}, io::{self, Read}, path::PathBuf};
+use fastcdc::v2020::StreamCDC;
+use serde::{Serialize, Deserialize};
+pub use crate::helpers::config::ChunkingConfig;
+use crate::helpers::{copy, outcome::Outcome, progress, storage::{self, ObjectFormat, StorageBackend}};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// a chunked file's manifest is stored as <hash[..2]>/<hash[2..]>.chunks,
+// and its chunks as objects addressed by their own blake3 hash; manifests are never
+// compressed or encrypted, as they only hold hashes and sizes
+pub const MANIFEST_SUFFIX: &str = ".chunks";
+
+// the chunks a file is stored as, in order
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Manifest {
+    pub blake3_checksum: String,
+    pub size: u64,
+    pub chunks: Vec<Chunk>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Chunk {
+    pub blake3_checksum: String,
+    pub size: u64,
+}
+
+// storage key of the manifest for a file hash
+pub fn manifest_key(hash: &String) -> String {
+    format!("{hash}{MANIFEST_SUFFIX}")
+}
+
+pub fn is_manifest_key(key: &String) -> bool {
+    key.ends_with(MANIFEST_SUFFIX)
+}
+
+// minimum, average, and maximum chunk sizes: a quarter of and four times the configured size
+fn chunk_sizes(chunking: &ChunkingConfig) -> (u32, u32, u32) {
+    let avg_size = chunking.chunk_size;
+    let min_size = (avg_size / 4).max(fastcdc::v2020::MINIMUM_MIN);
+    let max_size = avg_size.saturating_mul(4).min(fastcdc::v2020::MAXIMUM_MAX);
+    (min_size, avg_size, max_size)
+}
+
+// split the file into chunks, copy the ones storage doesn't have yet, then store the manifest;
+// Present if someone else stored the manifest first
+pub fn put(backend: &dyn StorageBackend, local_path: &PathBuf, hash: &String, format: &ObjectFormat, chunking: &ChunkingConfig) -> Result<Outcome> {
+    // each chunk is written here before it is copied to storage, and then the manifest
+    let temp_path = storage::staging_path(backend, &manifest_key(hash), local_path)?;
+    let result = put_impl(backend, local_path, hash, format, chunking, &temp_path);
+    let _ = fs::remove_file(&temp_path);
+    result
+}
+
+fn put_impl(backend: &dyn StorageBackend, local_path: &PathBuf, hash: &String, format: &ObjectFormat, chunking: &ChunkingConfig, temp_path: &PathBuf) -> Result<Outcome> {
+    let file = File::open(local_path).map_err(|e|
+        format!("could not open source file: {}\n{e}", local_path.display())
+    )?;
+
+    let size = file.metadata().ok().map(|metadata| metadata.len());
+
+    // progress is reported for the whole file rather than each chunk
+    let (min_size, avg_size, max_size) = chunk_sizes(chunking);
+    let mut chunks: Vec<Chunk> = Vec::new();
+    for chunk in StreamCDC::new(progress::Reader::new(file, size), min_size, avg_size, max_size) {
+        let chunk = chunk.map_err(|e| format!("could not split {} into chunks: {e}", local_path.display()))?;
+        let chunk_hash = blake3::hash(&chunk.data).to_string();
+
+        // unchanged chunks are already in storage from earlier versions; they're only looked for in the format they'd
+        // be written in, so a chunk stored in another, e.g. before compression was turned on, is stored again
+        let chunk_key = format.key(&chunk_hash);
+        if backend.exists(&chunk_key)? {
+            storage::touch_key(backend, &chunk_key);
+        }
+        else {
+            fs::write(temp_path, &chunk.data).map_err(|e|
+                format!("could not write chunk to {}: {e}", temp_path.display())
+            )?;
+            storage::put(backend, temp_path, &chunk_hash, format).map_err(|e|
+                format!("could not copy chunk {chunk_hash} of {}: {e}", local_path.display())
+            )?;
+        }
+        chunks.push(Chunk{blake3_checksum: chunk_hash, size: chunk.length as u64});
+    }
+
+    // the manifest goes last, so a file is only found in storage once all of its chunks are
+    let manifest = Manifest{
+        blake3_checksum: hash.clone(),
+        size: chunks.iter().map(|chunk| chunk.size).sum(),
+        chunks,
+    };
+    fs::write(temp_path, serde_json::to_string_pretty(&manifest)?).map_err(|e|
+        format!("could not write manifest to {}: {e}", temp_path.display())
+    )?;
+    backend.put(temp_path, &manifest_key(hash))
+}
+
+pub fn read_manifest(backend: &dyn StorageBackend, hash: &String) -> Result<Manifest> {
+    let mut contents = String::new();
+    backend.open(&manifest_key(hash))?.read_to_string(&mut contents).map_err(|e|
+        format!("could not read {}: {e}", backend.location(&manifest_key(hash)))
+    )?;
+    Ok(serde_json::from_str(&contents).map_err(|e|
+        format!("could not parse {}: {e}", backend.location(&manifest_key(hash)))
+    )?)
+}
+
+// reassemble the file from its chunks at local_path, if it matches the hash
+pub fn get(backend: &dyn StorageBackend, hash: &String, local_path: &PathBuf) -> Result<()> {
+    copy::write_verified(&mut open(backend, hash)?, hash, local_path)
+}
+
+// read the file's contents from its chunks, without checking them
+pub fn open<'a>(backend: &'a dyn StorageBackend, hash: &String) -> Result<Box<dyn Read + 'a>> {
+    let manifest = read_manifest(backend, hash)?;
+    Ok(Box::new(ChunkReader{backend, chunks: manifest.chunks.into_iter(), current: None}))
+}
+
+// true if the file at path is the manifest for the given hash
+pub fn manifest_matches(path: &PathBuf, hash: &String) -> bool {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str::<Manifest>(&contents).ok())
+        .map_or(false, |manifest| manifest.blake3_checksum == *hash)
+}
+
+// check a manifest uploaded to a storage server before writing it to dest_path, putting it in place as placement says
+pub fn write_manifest(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf, placement: &copy::Placement) -> Result<Outcome> {
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    let manifest: Manifest = serde_json::from_str(&contents).map_err(|e|
+        format!("contents for {} are not a chunk manifest: {e}", dest_path.display())
+    )?;
+    if manifest.blake3_checksum != *hash {
+        return Err(format!("manifest for {} is for {}", dest_path.display(), manifest.blake3_checksum).into())
+    }
+
+    fs::create_dir_all(dest_path
+        .parent()
+        .ok_or_else(|| format!("could not access parent of copy destination: {}", dest_path.display()))?)?;
+    let temp_path = copy::temp_path(dest_path);
+    let result = fs::write(&temp_path, contents)
+        .map_err(|e| format!("could not write {}: {e}", dest_path.display()).into())
+        .and_then(|_| copy::place(&temp_path, dest_path, placement));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+// reads the chunks one after another, opening each only when the one before it is used up
+struct ChunkReader<'a> {
+    backend: &'a dyn StorageBackend,
+    chunks: std::vec::IntoIter<Chunk>,
+    current: Option<Box<dyn Read + 'a>>,
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let current = match &mut self.current {
+                Some(current) => current,
+                None => match self.chunks.next() {
+                    None => return Ok(0),
+                    Some(chunk) => self.current.insert(
+                        storage::open(self.backend, &chunk.blake3_checksum).map_err(|e| io::Error::other(e.to_string()))?
+                    ),
+                },
+            };
+            let bytes_read = current.read(buf)?;
+            if bytes_read > 0 {
+                return Ok(bytes_read)
+            }
+            self.current = None;
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use std::{fs::{self, File}, io::{self, Read}, path::PathBuf};
+use crate::helpers::{copy, outcome::Outcome, progress};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// compressed objects are stored beside raw ones as <hash[..2]>/<hash[2..]>.zst,
+// still addressed by the blake3 hash of the uncompressed contents
+pub const ZSTD_SUFFIX: &str = ".zst";
+
+// storage key of the compressed object for a hash
+pub fn zstd_key(hash: &String) -> String {
+    format!("{hash}{ZSTD_SUFFIX}")
+}
+
+pub fn is_zstd_key(key: &String) -> bool {
+    key.ends_with(ZSTD_SUFFIX)
+}
+
+// compress the file at local_path into temp_path, to upload; the caller removes it once it's uploaded
+pub fn compress_to_temp(local_path: &PathBuf, level: i32, temp_path: &PathBuf) -> Result<()> {
+    let result = (|| -> Result<()> {
+        let src_file = File::open(local_path)?;
+        let size = src_file.metadata().ok().map(|metadata| metadata.len());
+        let temp_file = File::create(temp_path)?;
+        zstd::stream::copy_encode(progress::Reader::new(src_file, size), temp_file, level)?;
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(temp_path);
+        return Err(format!("could not compress {}: {e}", local_path.display()).into())
+    }
+    Ok(())
+}
+
+// decompress an object as it is read
+pub fn decoder<'a>(reader: Box<dyn Read + 'a>) -> Result<Box<dyn Read + 'a>> {
+    Ok(Box::new(zstd::stream::read::Decoder::new(reader)?))
+}
+
+// true if the compressed object at path decompresses to the given blake3 hash
+pub fn object_matches(path: &PathBuf, hash: &String) -> bool {
+    decompressed_hash(path, path).map_or(false, |actual_hash| actual_hash == *hash)
+}
+
+// blake3 hash of what the compressed file at path decompresses to; errors name dest_path, where it's going
+fn decompressed_hash(path: &PathBuf, dest_path: &PathBuf) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut decoder = zstd::stream::read::Decoder::new(File::open(path)?).map_err(|e|
+        format!("contents for {} are not zstd-compressed: {e}", dest_path.display())
+    )?;
+    io::copy(&mut decoder, &mut hasher).map_err(|e|
+        format!("contents for {} could not be decompressed: {e}", dest_path.display())
+    )?;
+    Ok(hasher.finalize().to_string())
+}
+
+// write compressed contents to dest_path only if they decompress to the given blake3 hash,
+// putting them in place as placement says; nothing is left at dest_path or beside it otherwise
+pub fn write_verified(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf, placement: &copy::Placement) -> Result<Outcome> {
+    copy::write_placed_encoded(reader, hash, dest_path, placement, &|temp_path| decompressed_hash(temp_path, dest_path))
+}
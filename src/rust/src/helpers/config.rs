@@ -2,18 +2,230 @@ use file_owner::Group;
 use serde::{Serialize, Deserialize};
 use std::fs;
 use std::path::PathBuf;
-use crate::helpers::error::{BatchError, BatchErrorType};
+use crate::helpers::{error::{BatchError, BatchErrorType}, hash::DigestAlgorithm};
 
 pub type Result<T> = core::result::Result<T, Error>;
 pub type Error = Box<dyn std::error::Error>;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug)]
 pub struct Config {
+    #[serde(default)]
     pub storage_dir: PathBuf,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub permissions: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub group: Option<String>
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Backend::is_local")]
+    pub backend: Backend,
+    // compress objects as they are added; objects already in storage are read either way
+    #[serde(default, skip_serializing_if = "Compression::is_none")]
+    pub compression: Compression,
+    // zstd level from 1 (fastest) to 22 (smallest), 3 by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression_level: Option<i32>,
+    // store large files as content-defined chunks, so a new version only adds the chunks that changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunking: Option<ChunkingConfig>,
+    // further storage locations, tried in order after storage_dir/backend
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub replicas: Vec<Replica>,
+    // how dvs_get puts files in the workspace
+    #[serde(default, skip_serializing_if = "Materialization::is_copy")]
+    pub materialization: Materialization,
+    // files added, retrieved, or checked at once; one per core by default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workers: Option<usize>,
+    // encrypt objects as they are added, with a key kept outside the repository; objects already in storage are read either way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encryption: Option<EncryptionConfig>,
+    // record the modification time and mode of files as they are added
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub record_attributes: bool,
+    // how dvs_get sets the modification time of the files it copies, if it was recorded
+    #[serde(default, skip_serializing_if = "Mtime::is_touch")]
+    pub mtime: Mtime,
+    // digests recorded in .dvs files besides the blake3 hash, e.g. sha256 for partners who check files with sha256sum
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub digests: Vec<DigestAlgorithm>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ChunkingConfig {
+    // files smaller than this are stored whole
+    #[serde(default = "default_min_file_size")]
+    pub min_file_size: u64,
+    // average chunk size in bytes
+    #[serde(default = "default_chunk_size")]
+    pub chunk_size: u32,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    #[default]
+    None,
+    Zstd,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EncryptionConfig {
+    // names the key, read from the DVS_KEY_<KEY_ID> environment variable or ~/.config/dvs/keys/<key_id>
+    pub key_id: String,
+}
+
+// linking and reflinking need the object stored whole and uncompressed on a locally mounted filesystem;
+// otherwise, or if the link can't be made, the file is copied
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Materialization {
+    #[default]
+    Copy,
+    // copy-on-write copy, on filesystems that support it (btrfs, xfs, apfs, ...)
+    Reflink,
+    // as with Symlink, only objects stored read-only are linked; others are copied
+    Hardlink,
+    // only objects stored read-only are linked, so the file can't be edited through the link; others are copied
+    Symlink,
+}
+
+// linked files are the objects in storage, so their modification time and mode are left as they are
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mtime {
+    // the time the file was copied, so make and similar tools see it as new
+    #[default]
+    Touch,
+    // the time recorded when the file was added
+    Restore,
+}
+
+// a copy of the storage, configured like the primary one
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Replica {
+    // storage directory of a local replica
+    #[serde(default)]
+    pub storage_dir: PathBuf,
+    #[serde(default, skip_serializing_if = "Backend::is_local")]
+    pub backend: Backend,
+    // files that can't be copied to a required replica aren't added
+    #[serde(default = "default_required")]
+    pub required: bool,
+}
+
+// where added files are stored; selected with the `backend` entry in dvs.yaml
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Backend {
+    // storage_dir on a locally mounted filesystem
+    #[default]
+    Local,
+    // S3-compatible object storage
+    S3(S3Config),
+    // a storage directory on a remote host, over ssh
+    Sftp(SftpConfig),
+    // a storage directory served over http(s); read-only
+    Http(HttpConfig),
+    // a storage directory served by `dvs_serve`
+    Server(HttpConfig),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct S3Config {
+    // e.g. https://s3.us-east-1.amazonaws.com or http://localhost:9000
+    pub endpoint: String,
+    pub bucket: String,
+    // objects are stored under <bucket>/<prefix>/<hash[..2]>/<hash[2..]>
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub prefix: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    #[serde(default)]
+    pub credentials: CredentialsSource,
+    // profile in the shared credentials file when credentials is `profile`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+}
+
+// where S3 credentials are read from; never stored in dvs.yaml
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CredentialsSource {
+    // AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY, and AWS_SESSION_TOKEN
+    #[default]
+    Env,
+    // the shared credentials file: AWS_SHARED_CREDENTIALS_FILE or ~/.aws/credentials
+    Profile,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SftpConfig {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    // defaults to the local user name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    // storage directory on the remote host
+    pub storage_dir: PathBuf,
+    // private key to authenticate with; the ssh agent is used otherwise
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identity_file: Option<PathBuf>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct HttpConfig {
+    // base url the storage directory is served from
+    pub url: String,
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+// 64 MiB
+fn default_min_file_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+// 1 MiB
+fn default_chunk_size() -> u32 {
+    1024 * 1024
+}
+
+fn default_required() -> bool {
+    true
+}
+
+fn default_region() -> String {
+    String::from("us-east-1")
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+impl Compression {
+    pub fn is_none(&self) -> bool {
+        *self == Compression::None
+    }
+}
+
+impl Materialization {
+    pub fn is_copy(&self) -> bool {
+        *self == Materialization::Copy
+    }
+}
+
+impl Mtime {
+    pub fn is_touch(&self) -> bool {
+        *self == Mtime::Touch
+    }
+}
+
+impl Backend {
+    pub fn is_local(&self) -> bool {
+        *self == Backend::Local
+    }
 }
 
 pub fn read(root_dir: &PathBuf) -> std::result::Result<Config, BatchError> {
@@ -49,6 +261,19 @@ pub fn get_mode_u32(permissions: &i32) -> std::result::Result<u32, BatchError> {
     })?)
 }
 
+pub fn get_chunking(chunking: &Option<ChunkingConfig>) -> std::result::Result<Option<ChunkingConfig>, BatchError> {
+    if let Some(chunking) = chunking {
+        let (min, max) = (fastcdc::v2020::AVERAGE_MIN, fastcdc::v2020::AVERAGE_MAX);
+        if chunking.chunk_size < min || chunking.chunk_size > max {
+            return Err(BatchError{
+                error: BatchErrorType::ChunkingInvalid,
+                error_message: format!("change chunk_size: {} in dvs.yaml, must be between {min} and {max} bytes", chunking.chunk_size)
+            })
+        }
+    }
+    Ok(chunking.clone())
+}
+
 pub fn get_group(group_name: &String) -> std::result::Result<Option<Group>, BatchError> {
     if group_name == "" {
         return Ok(None)
@@ -63,6 +288,53 @@ pub fn get_group(group_name: &String) -> std::result::Result<Option<Group>, Batc
     };
 }
 
+// workers given for a call, e.g. dvs_add(workers = 4), override the ones in dvs.yaml
+pub fn get_workers(workers: Option<usize>, conf_workers: &Option<usize>) -> std::result::Result<Option<usize>, BatchError> {
+    if workers == Some(0) {
+        return Err(BatchError{
+            error: BatchErrorType::WorkersInvalid,
+            error_message: String::from("workers: 0 not valid, must be at least 1")
+        })
+    }
+    if workers.is_none() && *conf_workers == Some(0) {
+        return Err(BatchError{
+            error: BatchErrorType::WorkersInvalid,
+            error_message: String::from("change workers: 0 in dvs.yaml, must be at least 1")
+        })
+    }
+    Ok(workers.or(*conf_workers))
+}
+
+// materialization override from dvs_get, e.g. "symlink"
+pub fn parse_materialization(name: &str) -> std::result::Result<Materialization, BatchError> {
+    serde_yaml::from_str(name).map_err(|_|
+        BatchError{
+            error: BatchErrorType::MaterializationInvalid,
+            error_message: format!("materialization: {name} not valid, must be one of copy, reflink, hardlink, symlink")
+        }
+    )
+}
+
+// mtime override from dvs_get, e.g. "restore"
+pub fn parse_mtime(name: &str) -> std::result::Result<Mtime, BatchError> {
+    serde_yaml::from_str(name).map_err(|_|
+        BatchError{
+            error: BatchErrorType::MtimeInvalid,
+            error_message: format!("mtime: {name} not valid, must be one of touch, restore")
+        }
+    )
+}
+
+// algorithm given to dvs_verify, e.g. "sha256"
+pub fn parse_digest_algorithm(name: &str) -> std::result::Result<DigestAlgorithm, BatchError> {
+    serde_yaml::from_str(name).map_err(|_|
+        BatchError{
+            error: BatchErrorType::DigestAlgorithmInvalid,
+            error_message: format!("algorithm: {name} not valid, must be one of blake3, sha256, md5")
+        }
+    )
+}
+
 pub fn get_storage_dir(storage_dir: &PathBuf) -> std::result::Result<PathBuf, BatchError>{
     Ok(storage_dir.canonicalize().map_err(|e|
             BatchError{
@@ -1,5 +1,5 @@
-use std::{fs::{self, File}, path::PathBuf, os::unix::fs::PermissionsExt};
-use crate::helpers::{error::{FileError, FileErrorType}, file::{get_absolute_path, get_relative_path_to_wd}};
+use std::{fmt, fs::{self, File}, io::{self, ErrorKind, Read, Write}, path::PathBuf, os::unix::fs::PermissionsExt, sync::atomic::{AtomicUsize, Ordering}};
+use crate::helpers::{error::{FileError, FileErrorType}, file::{get_absolute_path, get_relative_path_to_wd}, outcome::Outcome, progress::{self, Stage}};
 use file_owner::{Group, PathExt};
 
 use super::file;
@@ -7,24 +7,170 @@ use super::file;
 pub type Result<T> = core::result::Result<T, Error>;
 pub type Error = Box<dyn std::error::Error>;
 
-pub fn copy_impl(src_path: &PathBuf, dest_path: &PathBuf) -> Result<()> {
+// copy the file at src_path to dest_path only if its contents hash to the given blake3 hash,
+// writing a temporary file beside dest_path and moving it into place once it's complete
+pub fn copy_impl(src_path: &PathBuf, dest_path: &PathBuf, hash: &String) -> Result<()> {
     
     // Ignore .. and . paths
     if *src_path == PathBuf::from(r"..") || *src_path == PathBuf::from(r".") {
-        return Err(format!("copy failed: \"..\" and \".\" paths not parsable").into());
+        return Err("copy failed: \"..\" and \".\" paths not parsable".into());
     }
 
     // Open source file
     let src_file = File::open(src_path).map_err(|e|
         format!("could not open source file: {}\n{e}", src_path.display())
     )?;
+    let size = src_file.metadata().ok().map(|metadata| metadata.len());
 
-    // Get file size
-    let _ = src_file.metadata().map_err(|e|
-        format!("could not get file size: {}\n{e}", src_path.display())
-    )?.len();
-   
+    write_verified(&mut progress::Reader::new(src_file, size), hash, dest_path)
+}
+
+// contents that don't hash to what they were expected to, e.g. a file that changed while it was copied
+#[derive(Debug)]
+pub struct HashMismatch {
+    pub dest_path: PathBuf,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl fmt::Display for HashMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "contents for {} do not match their hash: expected {}, got {}", self.dest_path.display(), self.expected, self.actual)
+    }
+}
+
+impl std::error::Error for HashMismatch {}
+
+// make a rename into dest_path's directory durable
+pub fn sync_parent(dest_path: &PathBuf) {
+    if let Some(parent) = dest_path.parent() {
+        let _ = File::open(parent).and_then(|dir| dir.sync_all());
+    }
+}
+
+// distinguishes temporary files written by different threads of the same process
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// temporary file next to dest_path that a copy is written to before it is moved into place
+pub fn temp_path(dest_path: &PathBuf) -> PathBuf {
+    let file_name = dest_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let count = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    dest_path.with_file_name(format!(".{file_name}.{}-{count}.tmp", std::process::id()))
+}
+
+// how a complete, verified temporary file is put at its destination
+pub enum Placement<'a> {
+    // replace whatever is there, e.g. a file in the workspace
+    Replace,
+    // a storage object: given its permissions and group while it's still the writer's own temporary file,
+    // then linked into place only if nobody else has put it there first. objects are named by their
+    // contents, so when two people add the same file at once, whichever gets there first wins and the
+    // other's copy is dropped, rather than one replacing an object the other is setting permissions on.
+    // an object already there is kept only if check says it's a good copy, and replaced otherwise
+    Object{permissions: u32, group: &'a Option<Group>, check: &'a dyn Fn(&PathBuf) -> bool},
+}
+
+// put temp_path at dest_path as placement says; temp_path is gone afterwards unless this fails.
+// Present if a good object was already there, so this copy was dropped, and Copied otherwise
+pub fn place(temp_path: &PathBuf, dest_path: &PathBuf, placement: &Placement) -> Result<Outcome> {
+    let mut outcome = Outcome::Copied;
+    match placement {
+        Placement::Replace => {
+            fs::rename(temp_path, dest_path).map_err(|e|
+                format!("could not move {} to {}: {e}", temp_path.display(), dest_path.display())
+            )?;
+        }
+        Placement::Object{permissions, group, check} => {
+            set_file_permissions(permissions, temp_path)?;
+            set_group(group, temp_path)?;
+
+            // a hard link can't replace an existing file, unlike a rename
+            match fs::hard_link(temp_path, dest_path) {
+                Ok(()) => {}
+                // someone else's copy, or a corrupt one, which this complete and verified copy replaces
+                Err(e) if e.kind() == ErrorKind::AlreadyExists || dest_path.exists() => {
+                    if check(dest_path) {
+                        outcome = Outcome::Present;
+                    }
+                    else {
+                        fs::rename(temp_path, dest_path).map_err(|e|
+                            format!("could not replace corrupt {} with {}: {e}", dest_path.display(), temp_path.display())
+                        )?;
+                    }
+                }
+                // filesystems without hard links
+                Err(link_error) => {
+                    fs::rename(temp_path, dest_path).map_err(|e|
+                        format!("could not link or move {} to {}: {link_error}; {e}", temp_path.display(), dest_path.display())
+                    )?;
+                }
+            }
+            let _ = fs::remove_file(temp_path);
+        }
+    }
+    sync_parent(dest_path);
+    Ok(outcome)
+}
+
+fn write_verified_impl(reader: &mut dyn Read, hash: &String, temp_path: &PathBuf, dest_path: &PathBuf, placement: &Placement, decoded_hash: Option<&dyn Fn(&PathBuf) -> Result<String>>) -> Result<Outcome> {
+    let mut temp_file = File::create(temp_path).map_err(|e|
+        format!("could not create file at {} \n{e}", temp_path.display())
+    )?;
+
+    // hash the contents as they are written; a cancelled copy stops here and its temporary file is removed
+    let stage = progress::begin(Stage::Copying, None);
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    loop {
+        let bytes_read = reader.read(&mut buffer).map_err(|e|
+            format!("could not read contents for {}: {e}", dest_path.display())
+        )?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        temp_file.write_all(&buffer[..bytes_read]).map_err(|e|
+            format!("could not write to {}: {e}", temp_path.display())
+        )?;
+        stage.advance(bytes_read as u64)?;
+    }
+    temp_file.sync_all()?;
 
+    let actual_hash = match decoded_hash {
+        Some(decoded_hash) => decoded_hash(temp_path)?,
+        None => hasher.finalize().to_string(),
+    };
+    if actual_hash != *hash {
+        return Err(Box::new(HashMismatch{dest_path: dest_path.clone(), expected: hash.clone(), actual: actual_hash}));
+    }
+
+    place(temp_path, dest_path, placement)
+}
+
+// write the contents of reader to dest_path only if they hash to the given blake3 hash;
+// nothing is left at dest_path or beside it otherwise
+pub fn write_verified(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf) -> Result<()> {
+    write_placed(reader, hash, dest_path, &Placement::Replace).map(|_| ())
+}
+
+// true if the file at path hashes to the given blake3 hash
+pub fn file_matches(path: &PathBuf, hash: &String) -> bool {
+    let mut hasher = blake3::Hasher::new();
+    File::open(path).and_then(|mut file| io::copy(&mut file, &mut hasher)).is_ok() && hasher.finalize().to_string() == *hash
+}
+
+// write_verified, putting the file at dest_path as placement says
+pub fn write_placed(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf, placement: &Placement) -> Result<Outcome> {
+    write_staged(reader, hash, dest_path, placement, None)
+}
+
+// write_placed for contents stored encoded, e.g. compressed: decoded_hash gives the hash of what the complete
+// temporary file decodes to, which is checked instead of the hash of the contents as written
+pub fn write_placed_encoded(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf, placement: &Placement, decoded_hash: &dyn Fn(&PathBuf) -> Result<String>) -> Result<Outcome> {
+    write_staged(reader, hash, dest_path, placement, Some(decoded_hash))
+}
+
+fn write_staged(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf, placement: &Placement, decoded_hash: Option<&dyn Fn(&PathBuf) -> Result<String>>) -> Result<Outcome> {
     // ensure destination exists
     fs::create_dir_all(dest_path
         .parent()
@@ -32,29 +178,12 @@ pub fn copy_impl(src_path: &PathBuf, dest_path: &PathBuf) -> Result<()> {
         .map_err(|e| format!("could not access copy destination: {} \n{e}", dest_path.display())
     )?;
 
-    // create destination file
-    File::create(dest_path).map_err(|e|
-        format!("could not create file at {} \n{e}", dest_path.display())
-    )?;
-
-    // Copy the file
-    fs::copy(src_path, dest_path).map_err(|e|
-        format!("could not copy file {} to {}: {e}", src_path.display(), dest_path.display())
-    )?;
-
-    Ok(())
-}
-
-pub fn copy(local_path: &PathBuf, storage_path: &PathBuf) -> std::result::Result<(), FileError> {
-    Ok(copy_impl(local_path, storage_path).map_err(|e|
-            FileError{
-                relative_path: file::try_to_get_rel_path(local_path),
-                absolute_path: file::try_to_get_abs_path(local_path),
-                error: FileErrorType::FileNotCopied,
-                error_message: Some(e.to_string()),
-                input: local_path.clone()
-            }
-        )?)
+    let temp_path = temp_path(dest_path);
+    let result = write_verified_impl(reader, hash, &temp_path, dest_path, placement, decoded_hash);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
 }
 
 pub fn set_file_permissions(mode: &u32, local_path: &PathBuf) -> std::result::Result<(), FileError> {
@@ -86,14 +215,3 @@ pub fn set_group(group: &Option<Group>, local_path: &PathBuf) -> std::result::Re
     }
     Ok(())
 }
-
-pub fn copy_file_to_storage_directory(local_path: &PathBuf, storage_path: &PathBuf, permissions: &u32, group: &Option<Group>) -> std::result::Result<(), FileError> {
-    // copy
-    copy(local_path, storage_path)?;
-
-    // set file permissions
-    set_file_permissions(permissions, storage_path)?; // [MAN-ADD-001]
-
-    // set group (if specified)
-    Ok(set_group(group, storage_path)?)
-}
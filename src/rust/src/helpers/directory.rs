@@ -0,0 +1,139 @@
+use std::{collections::{BTreeMap, HashSet}, path::PathBuf};
+use blake3::Hasher;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::helpers::{batch, error::{FileError, FileErrorType}, file, hash, outcome::Status};
+
+// a file in a directory tracked as a unit, as listed in the directory's .dvs file
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub struct Member {
+    // relative to the directory, with / between components
+    pub path: String,
+    pub size: u64,
+    pub blake3_checksum: String,
+}
+
+// status of one file of a tracked directory against the version recorded
+#[derive(PartialEq, Debug)]
+pub struct MemberStatus {
+    pub path: String,
+    pub status: Status,
+    // as recorded, or of the local file if it isn't in the version recorded
+    pub size: u64,
+    pub blake3_checksum: String,
+}
+
+// recorded as a directory's blake3_checksum: a hash over its members, sorted by path, so it changes
+// when any file is added, removed, renamed or changed
+pub fn tree_hash(members: &Vec<Member>) -> String {
+    let mut hasher = Hasher::new();
+    hasher.update(b"dvs tree 1\0");
+    for member in members {
+        // paths can't contain NUL, so each entry is unambiguous
+        hasher.update(format!("{}\0{}\0{}\0", member.path, member.size, member.blake3_checksum).as_bytes());
+    }
+    hasher.finalize().to_string()
+}
+
+// member paths come from .dvs files, which anyone who can commit to the project can write,
+// so they must name a file inside the directory: relative, without empty, . or .. components
+pub fn check_member_path(path: &String) -> std::result::Result<(), String> {
+    let valid = !path.contains('\0') && path.split('/').all(|component| !component.is_empty() && component != "." && component != "..");
+    if !valid {
+        return Err(format!("member path \"{path}\" invalid: paths are relative to the directory, without empty, . or .. components"))
+    }
+    Ok(())
+}
+
+// where a member of the directory at local_path goes, checked so it can't be written outside the directory,
+// either by its path or through a symlinked directory in the workspace
+pub fn member_path(local_path: &PathBuf, member: &Member) -> std::result::Result<PathBuf, FileError> {
+    let path_error = |message: String| FileError{
+        relative_path: file::try_to_get_rel_path(local_path),
+        absolute_path: file::try_to_get_abs_path(local_path),
+        error: FileErrorType::MemberPathInvalid,
+        error_message: Some(message),
+        input: local_path.clone()
+    };
+    check_member_path(&member.path).map_err(path_error)?;
+
+    let mut path = local_path.clone();
+    let components: Vec<&str> = member.path.split('/').collect();
+    for (i, component) in components.iter().enumerate() {
+        path.push(component);
+        let is_symlink = path.symlink_metadata().map_or(false, |metadata| metadata.file_type().is_symlink());
+        if is_symlink && i + 1 < components.len() {
+            return Err(path_error(format!("{} is a symlink, so {} would be written outside the directory", path.display(), member.path)))
+        }
+    }
+    Ok(path)
+}
+
+// the files under a directory with their sizes and hashes, sorted by path; symlinks to files are followed, since a
+// directory retrieved with materialization: symlink is made of them, but empty and symlinked directories aren't tracked
+pub fn get_members(local_path: &PathBuf) -> std::result::Result<Vec<Member>, FileError> {
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for entry in WalkDir::new(local_path) {
+        let entry = entry.map_err(|e|
+            FileError{
+                relative_path: file::try_to_get_rel_path(local_path),
+                absolute_path: file::try_to_get_abs_path(local_path),
+                error: FileErrorType::HashNotFound,
+                error_message: Some(format!("could not list the files in {}: {e}", local_path.display())),
+                input: local_path.clone()
+            }
+        )?;
+        let path = entry.path().to_path_buf();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+
+    // hashed in parallel, on the threads of the batch the directory is part of
+    let mut members = batch::map_within(&paths, |path| {
+        let relative_path = path.strip_prefix(local_path).unwrap_or(path)
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join("/");
+        Ok(Member{
+            path: relative_path,
+            size: file::get_file_size(path)?,
+            blake3_checksum: hash::get_file_hash(path)?,
+        })
+    }).into_iter().collect::<std::result::Result<Vec<Member>, FileError>>()?;
+    members.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(members)
+}
+
+// each recorded member as current, unsynced or absent locally, and each local file that isn't one as untracked, by path
+pub fn member_statuses(recorded: &Vec<Member>, local: &Vec<Member>) -> Vec<MemberStatus> {
+    let local_hashes: BTreeMap<&String, &String> = local.iter().map(|member| (&member.path, &member.blake3_checksum)).collect();
+    let recorded_paths: HashSet<&String> = recorded.iter().map(|member| &member.path).collect();
+
+    let mut statuses: Vec<MemberStatus> = recorded
+        .iter()
+        .map(|member| MemberStatus{
+            path: member.path.clone(),
+            status: match local_hashes.get(&member.path) {
+                None => Status::Absent,
+                Some(hash) if **hash == member.blake3_checksum => Status::Current,
+                Some(_) => Status::Unsynced,
+            },
+            size: member.size,
+            blake3_checksum: member.blake3_checksum.clone(),
+        })
+        .collect();
+    statuses.extend(local
+        .iter()
+        .filter(|member| !recorded_paths.contains(&member.path))
+        .map(|member| MemberStatus{
+            path: member.path.clone(),
+            status: Status::Untracked,
+            size: member.size,
+            blake3_checksum: member.blake3_checksum.clone(),
+        })
+    );
+    statuses.sort_by(|a, b| a.path.cmp(&b.path));
+    statuses
+}
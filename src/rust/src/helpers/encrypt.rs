@@ -0,0 +1,322 @@
+use std::{env, fmt, fs::{self, File}, io::{self, BufRead, BufReader, Read, Write}, path::PathBuf};
+use chacha20poly1305::{aead::{rand_core::RngCore, stream::{DecryptorBE32, EncryptorBE32}, KeyInit, OsRng, Payload}, XChaCha20Poly1305};
+use crate::helpers::{copy, outcome::Outcome, progress};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// encrypted objects are stored beside raw ones as <hash[..2]>/<hash[2..]>.enc,
+// still addressed by the blake3 hash of the plaintext
+pub const ENCRYPTED_SUFFIX: &str = ".enc";
+
+// an encrypted object starts with MAGIC, a flags byte, the length of the key id, the key id,
+// and the nonce; the rest is the plaintext in SEGMENT_SIZE segments, each followed by its tag
+const MAGIC: &[u8; 7] = b"DVSENC1";
+// the plaintext was compressed with zstd before it was encrypted
+const FLAG_ZSTD: u8 = 1;
+const SEGMENT_SIZE: usize = 1024 * 1024;
+const TAG_SIZE: usize = 16;
+// XChaCha20Poly1305's 24 bytes less the 5 the STREAM construction uses to count segments
+const NONCE_SIZE: usize = 19;
+
+// storage key of the encrypted object for a hash
+pub fn encrypted_key(hash: &String) -> String {
+    format!("{hash}{ENCRYPTED_SUFFIX}")
+}
+
+pub fn is_encrypted_key(key: &String) -> bool {
+    key.ends_with(ENCRYPTED_SUFFIX)
+}
+
+// a 256-bit key, held outside the repository and named in objects by its id
+pub struct Key {
+    pub id: String,
+    key: chacha20poly1305::Key,
+}
+
+// never print the key itself
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Key").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+// environment variable a key can be given in: DVS_KEY_ and the key id in upper case, e.g. DVS_KEY_PROJECT_X for project-x
+pub fn key_env_var(key_id: &str) -> String {
+    let name: String = key_id.chars().map(|c| if c.is_ascii_alphanumeric() {c.to_ascii_uppercase()} else {'_'}).collect();
+    format!("DVS_KEY_{name}")
+}
+
+// the key with the given id as 64 hex characters, from its environment variable or else
+// from ~/.config/dvs/keys/<key_id> (in $XDG_CONFIG_HOME if it's set)
+pub fn load_key(key_id: &str) -> Result<Key> {
+    if key_id.is_empty() || key_id.len() > u8::MAX as usize || !key_id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) || key_id.starts_with('.') {
+        return Err(format!("invalid key id: {key_id}; use letters, digits, '-', '_' and '.'").into())
+    }
+
+    let env_var = key_env_var(key_id);
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("dvs")?;
+    let key_file = format!("keys/{key_id}");
+    let hex_key = match env::var(&env_var) {
+        Ok(hex_key) => hex_key,
+        Err(_) => match xdg_dirs.find_config_file(&key_file) {
+            Some(key_path) => fs::read_to_string(&key_path).map_err(|e|
+                format!("could not read key {key_id} from {}: {e}", key_path.display())
+            )?,
+            None => return Err(format!(
+                "key {key_id} not found: set {env_var} or write it to {}",
+                xdg_dirs.get_config_home().join(&key_file).display()
+            ).into()),
+        },
+    };
+
+    let bytes = hex::decode(hex_key.trim()).ok().filter(|bytes| bytes.len() == 32).ok_or_else(||
+        format!("key {key_id} is not 64 hex characters (32 bytes)")
+    )?;
+    Ok(Key{id: String::from(key_id), key: *chacha20poly1305::Key::from_slice(&bytes)})
+}
+
+fn header(flags: u8, key_id: &str, nonce: &[u8]) -> Vec<u8> {
+    let mut header = MAGIC.to_vec();
+    header.push(flags);
+    header.push(key_id.len() as u8);
+    header.extend_from_slice(key_id.as_bytes());
+    header.extend_from_slice(nonce);
+    header
+}
+
+// every segment is authenticated along with the header and the hash the object is stored under,
+// so neither can be changed and an object can't be passed off as another
+fn associated_data(header: &[u8], hash: &String) -> Vec<u8> {
+    [header, hash.as_bytes()].concat()
+}
+
+fn decrypt_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "could not decrypt: the object was changed, or encrypted with another key of the same id")
+}
+
+// encrypts what's written to it a segment at a time; finish writes the last segment
+struct EncryptWriter<W: Write> {
+    inner: W,
+    encryptor: EncryptorBE32<XChaCha20Poly1305>,
+    aad: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> Write for EncryptWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        // a full segment is only written once there's more after it, as the last one is marked
+        while self.buffer.len() > SEGMENT_SIZE {
+            let segment: Vec<u8> = self.buffer.drain(..SEGMENT_SIZE).collect();
+            let ciphertext = self.encryptor.encrypt_next(Payload{msg: &segment, aad: &self.aad}).map_err(|e|
+                io::Error::other(format!("could not encrypt: {e}"))
+            )?;
+            self.inner.write_all(&ciphertext)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> EncryptWriter<W> {
+    fn finish(self) -> io::Result<W> {
+        let mut inner = self.inner;
+        let ciphertext = self.encryptor.encrypt_last(Payload{msg: &self.buffer, aad: &self.aad}).map_err(|e|
+            io::Error::other(format!("could not encrypt: {e}"))
+        )?;
+        inner.write_all(&ciphertext)?;
+        Ok(inner)
+    }
+}
+
+// encrypt the file at local_path, compressing it first at the given zstd level if there is one,
+// into temp_path, to upload; the caller removes it once it's uploaded
+pub fn encrypt_to_temp(local_path: &PathBuf, hash: &String, key: &Key, level: Option<i32>, temp_path: &PathBuf) -> Result<()> {
+    let result = (|| -> Result<()> {
+        let src_file = File::open(local_path)?;
+        let size = src_file.metadata().ok().map(|metadata| metadata.len());
+        let mut reader = progress::Reader::new(src_file, size);
+        let mut temp_file = File::create(temp_path)?;
+
+        let mut nonce = [0u8; NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce);
+        let header = header(if level.is_some() {FLAG_ZSTD} else {0}, &key.id, &nonce);
+        temp_file.write_all(&header)?;
+
+        let mut writer = EncryptWriter{
+            inner: temp_file,
+            encryptor: EncryptorBE32::from_aead(XChaCha20Poly1305::new(&key.key), nonce.as_slice().into()),
+            aad: associated_data(&header, hash),
+            buffer: Vec::with_capacity(SEGMENT_SIZE * 2),
+        };
+        match level {
+            Some(level) => {
+                let mut encoder = zstd::stream::write::Encoder::new(writer, level)?;
+                io::copy(&mut reader, &mut encoder)?;
+                encoder.finish()?.finish()?;
+            }
+            None => {
+                io::copy(&mut reader, &mut writer)?;
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    })();
+
+    if let Err(e) = result {
+        let _ = fs::remove_file(temp_path);
+        return Err(format!("could not encrypt {}: {e}", local_path.display()).into())
+    }
+    Ok(())
+}
+
+// decrypts an object a segment at a time as it's read, failing on any segment that doesn't authenticate
+struct DecryptReader<'a> {
+    inner: BufReader<Box<dyn Read + 'a>>,
+    // None once the last segment is decrypted
+    decryptor: Option<DecryptorBE32<XChaCha20Poly1305>>,
+    aad: Vec<u8>,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl DecryptReader<'_> {
+    fn next_segment(&mut self) -> io::Result<()> {
+        let mut ciphertext = Vec::with_capacity(SEGMENT_SIZE + TAG_SIZE);
+        (&mut self.inner).take((SEGMENT_SIZE + TAG_SIZE) as u64).read_to_end(&mut ciphertext)?;
+
+        // the segment with nothing after it must have been encrypted as the last one, so a
+        // truncated object fails rather than passing for a shorter one
+        let is_last = self.inner.fill_buf()?.is_empty();
+        let payload = Payload{msg: &ciphertext, aad: &self.aad};
+        self.plaintext = match self.decryptor.take() {
+            Some(decryptor) if is_last => decryptor.decrypt_last(payload),
+            Some(mut decryptor) => {
+                let plaintext = decryptor.decrypt_next(payload);
+                self.decryptor = Some(decryptor);
+                plaintext
+            }
+            None => return Ok(()),
+        }.map_err(|_| decrypt_error())?;
+        self.position = 0;
+        Ok(())
+    }
+}
+
+impl Read for DecryptReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.plaintext.len() {
+            if self.decryptor.is_none() {
+                return Ok(0)
+            }
+            self.next_segment()?;
+        }
+        let bytes_read = buf.len().min(self.plaintext.len() - self.position);
+        buf[..bytes_read].copy_from_slice(&self.plaintext[self.position..self.position + bytes_read]);
+        self.position += bytes_read;
+        Ok(bytes_read)
+    }
+}
+
+// the flags, key id, and nonce at the start of an encrypted object, and the header bytes they were read from
+fn read_header(reader: &mut dyn Read) -> Result<(u8, String, Vec<u8>, Vec<u8>)> {
+    let mut fixed = [0u8; MAGIC.len() + 2];
+    reader.read_exact(&mut fixed).map_err(|e| format!("could not read encryption header: {e}"))?;
+    if fixed[..MAGIC.len()] != MAGIC[..] {
+        return Err("not an encrypted object".into())
+    }
+    let flags = fixed[MAGIC.len()];
+    let mut key_id = vec![0u8; fixed[MAGIC.len() + 1] as usize];
+    let mut nonce = vec![0u8; NONCE_SIZE];
+    reader.read_exact(&mut key_id).and_then(|_| reader.read_exact(&mut nonce)).map_err(|e|
+        format!("could not read encryption header: {e}")
+    )?;
+    let key_id = String::from_utf8(key_id).map_err(|_| "encryption header names an invalid key id")?;
+    Ok((flags, key_id.clone(), nonce.clone(), header(flags, &key_id, &nonce)))
+}
+
+// the id of the key an encrypted object was encrypted with
+pub fn key_id(reader: &mut dyn Read) -> Result<String> {
+    Ok(read_header(reader)?.1)
+}
+
+// decrypt, and decompress if need be, an object stored under the given hash as it is read,
+// with the key its header names
+pub fn decoder<'a>(mut reader: Box<dyn Read + 'a>, hash: &String) -> Result<Box<dyn Read + 'a>> {
+    let (flags, key_id, nonce, header) = read_header(&mut reader)?;
+    let key = load_key(&key_id)?;
+    let decrypted = DecryptReader{
+        inner: BufReader::with_capacity(SEGMENT_SIZE + TAG_SIZE, reader),
+        decryptor: Some(DecryptorBE32::from_aead(XChaCha20Poly1305::new(&key.key), nonce.as_slice().into())),
+        aad: associated_data(&header, hash),
+        plaintext: Vec::new(),
+        position: 0,
+    };
+    if flags & FLAG_ZSTD != 0 {
+        Ok(Box::new(zstd::stream::read::Decoder::new(decrypted)?))
+    }
+    else {
+        Ok(Box::new(decrypted))
+    }
+}
+
+// true if the object at path decrypts to the given hash; without the key only its header can be checked
+pub fn object_matches(path: &PathBuf, hash: &String) -> bool {
+    let key_id = match File::open(path).map_err(Error::from).and_then(|mut file| key_id(&mut file)) {
+        Ok(key_id) => key_id,
+        Err(_) => return false,
+    };
+    if load_key(&key_id).is_err() {
+        return true
+    }
+    decrypted_hash(path, hash, path).map_or(false, |actual_hash| actual_hash == *hash)
+}
+
+// blake3 hash of what the encrypted file at path decrypts to; errors name dest_path, where it's going
+fn decrypted_hash(path: &PathBuf, hash: &String, dest_path: &PathBuf) -> Result<String> {
+    let mut hasher = blake3::Hasher::new();
+    let mut decoder = decoder(Box::new(File::open(path)?), hash).map_err(|e|
+        format!("contents for {} could not be decrypted: {e}", dest_path.display())
+    )?;
+    io::copy(&mut decoder, &mut hasher).map_err(|e|
+        format!("contents for {} could not be decrypted: {e}", dest_path.display())
+    )?;
+    Ok(hasher.finalize().to_string())
+}
+
+// write an encrypted object whose contents come from a stream, e.g. an upload, to dest_path only if
+// they decrypt to the given blake3 hash; without the key only its header can be checked, so the
+// outcome is Unchecked and it is fully verified when it's retrieved
+pub fn write_object(reader: &mut dyn Read, hash: &String, dest_path: &PathBuf, placement: &copy::Placement) -> Result<Outcome> {
+    let (_, key_id, _, header) = read_header(reader).map_err(|e| format!("contents for {} are not encrypted: {e}", dest_path.display()))?;
+    let mut reader = io::Cursor::new(header).chain(reader);
+    if load_key(&key_id).is_ok() {
+        return copy::write_placed_encoded(&mut reader, hash, dest_path, placement, &|temp_path| decrypted_hash(temp_path, hash, dest_path))
+    }
+
+    fs::create_dir_all(dest_path
+        .parent()
+        .ok_or_else(|| format!("could not access parent of copy destination: {}", dest_path.display()))?)
+        .map_err(|e| format!("could not access copy destination: {} \n{e}", dest_path.display())
+    )?;
+
+    let temp_path = copy::temp_path(dest_path);
+    let result = (|| -> Result<Outcome> {
+        let mut temp_file = File::create(&temp_path).map_err(|e|
+            format!("could not create file at {} \n{e}", temp_path.display())
+        )?;
+        io::copy(&mut reader, &mut temp_file).map_err(|e|
+            format!("could not write to {}: {e}", temp_path.display())
+        )?;
+        temp_file.sync_all()?;
+        copy::place(&temp_path, dest_path, placement)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.map(|outcome| if outcome == Outcome::Copied {Outcome::Unchecked} else {outcome})
+}
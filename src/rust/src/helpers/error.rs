@@ -18,6 +18,14 @@ pub enum FileErrorType {
     FileNotCopied,
     MetadataNotLoaded,
     FileNotAdded,
+    StorageNotReachable,
+    HashMismatch,
+    StorageObjectCorrupt,
+    Cancelled,
+    AttributesNotFound,
+    AttributesNotRestored,
+    DigestNotRecorded,
+    MemberPathInvalid,
 }
 
 #[derive(Debug, Clone)]
@@ -61,6 +69,14 @@ impl FileErrorType {
             FileErrorType::FileNotCopied => String::from("file not copied"),
             FileErrorType::MetadataNotLoaded => String::from("metadata file not loaded"),
             FileErrorType::FileNotAdded => String::from("file not added"),
+            FileErrorType::StorageNotReachable => String::from("storage not reachable"),
+            FileErrorType::HashMismatch => String::from("hash mismatch"),
+            FileErrorType::StorageObjectCorrupt => String::from("storage object corrupt"),
+            FileErrorType::Cancelled => String::from("cancelled"),
+            FileErrorType::AttributesNotFound => String::from("file attributes not found"),
+            FileErrorType::AttributesNotRestored => String::from("file attributes not restored"),
+            FileErrorType::DigestNotRecorded => String::from("digest not recorded"),
+            FileErrorType::MemberPathInvalid => String::from("member path invalid"),
         }
     }
 }
@@ -75,6 +91,20 @@ pub enum BatchErrorType {
     StorageDirNotFound,
     PermissionsInvalid,
     AnyMetaFilesDNE,
+    StorageBackendInvalid,
+    ServerNotStarted,
+    ChunkingInvalid,
+    ReferencesNotRead,
+    LayoutInvalid,
+    LayoutNotMigrated,
+    MaterializationInvalid,
+    WorkersInvalid,
+    EncryptionKeyNotFound,
+    HashInvalid,
+    IndexNotRead,
+    AnnotationInvalid,
+    MtimeInvalid,
+    DigestAlgorithmInvalid,
 }
 
 
@@ -102,6 +132,20 @@ impl BatchErrorType {
             BatchErrorType::StorageDirNotFound => String::from("storage directory not found"),
             BatchErrorType::PermissionsInvalid => String::from("linux file permissions invalid"),
             BatchErrorType::AnyMetaFilesDNE => String::from("metadata file not found for at least one file"),
+            BatchErrorType::StorageBackendInvalid => String::from("storage backend invalid"),
+            BatchErrorType::ServerNotStarted => String::from("storage server not started"),
+            BatchErrorType::ChunkingInvalid => String::from("chunking invalid"),
+            BatchErrorType::ReferencesNotRead => String::from("referenced files not read"),
+            BatchErrorType::LayoutInvalid => String::from("storage layout invalid"),
+            BatchErrorType::LayoutNotMigrated => String::from("storage layout not migrated"),
+            BatchErrorType::MaterializationInvalid => String::from("materialization invalid"),
+            BatchErrorType::WorkersInvalid => String::from("workers invalid"),
+            BatchErrorType::EncryptionKeyNotFound => String::from("encryption key not found"),
+            BatchErrorType::HashInvalid => String::from("hash invalid"),
+            BatchErrorType::IndexNotRead => String::from("storage index not read"),
+            BatchErrorType::AnnotationInvalid => String::from("annotation invalid"),
+            BatchErrorType::MtimeInvalid => String::from("mtime invalid"),
+            BatchErrorType::DigestAlgorithmInvalid => String::from("digest algorithm invalid"),
         }
     }
 }
@@ -129,7 +173,8 @@ pub enum InitErrorType {
     ConfigNotCreated,
     GroupNotFound,
     PermissionsInvalid,
-    DirEmptyNotChecked
+    DirEmptyNotChecked,
+    LayoutNotWritten,
 }
 
 impl InitErrorType {
@@ -145,6 +190,7 @@ impl InitErrorType {
             InitErrorType::PermissionsInvalid => String::from("linux file permissions invalid"),
             InitErrorType::DirEmptyNotChecked => String::from("could not check if storage directory is empty"),
             InitErrorType::StorageDirPermsNotSet => String::from("storage directory permissions not set"),
+            InitErrorType::LayoutNotWritten => String::from("storage layout file not written"),
         }
     }
 }
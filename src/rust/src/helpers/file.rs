@@ -1,20 +1,57 @@
-use std::{fs::{self, File}, path::PathBuf};
+use std::{collections::BTreeMap, fs::{self, File}, os::unix::fs::{MetadataExt, PermissionsExt}, path::PathBuf, time::SystemTime};
+use chrono::{DateTime, SecondsFormat, Utc};
 use file_owner::PathExt;
 use serde::{Deserialize, Serialize};
-use crate::helpers::{repo, error::{FileError, FileErrorType, BatchError, BatchErrorType}};
+use crate::helpers::{repo, directory::{self, Member}, error::{FileError, FileErrorType, BatchError, BatchErrorType}};
 
 use super::repo::absolutize_result;
 
 pub type Result<T> = core::result::Result<T, Error>;
 pub type Error = Box<dyn std::error::Error>;
 
+// version of the .dvs files this version of dvs writes; files without one are version 0
+pub const METADATA_VERSION: u32 = 1;
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Metadata {
+    #[serde(default)]
+    pub metadata_version: u32,
     pub blake3_checksum: String,
     pub size: u64,
     pub add_time: String,
     pub message: String,
-    pub saved_by: String
+    pub saved_by: String,
+    // labels and named values describing the file, e.g. a study id; see helpers::annotation
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    // modification time (RFC 3339) and mode (octal) of the file when it was added, if record_attributes is set in dvs.yaml
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    // hex digests besides the blake3 hash, keyed by algorithm, e.g. {"sha256": "..."}; set with digests in dvs.yaml
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub digests: BTreeMap<String, String>,
+    // for a directory tracked as a unit, its files sorted by path; blake3_checksum is then their tree hash and size
+    // their total size, see helpers::directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub members: Option<Vec<Member>>,
+    // fields this version of dvs doesn't know, e.g. from a newer version; kept as they are when the file is rewritten
+    // for the same contents, and dropped when a new version of the file is added
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Metadata {
+    // hashes and sizes of the objects in storage holding this version: the file's own, or one per member of a directory
+    pub fn objects(&self) -> Vec<(String, u64)> {
+        match &self.members {
+            Some(members) => members.iter().map(|member| (member.blake3_checksum.clone(), member.size)).collect(),
+            None => vec![(self.blake3_checksum.clone(), self.size)],
+        }
+    }
 }
 
 fn save_error(local_path: &PathBuf, e: impl std::error::Error) -> FileError {
@@ -47,6 +84,9 @@ pub fn load_helper(path: &PathBuf) -> Result<Metadata> {
     let metadata_path_abs = metadata_path(path).canonicalize()?;
     let contents = fs::read_to_string(metadata_path_abs)?;
     let metadata: Metadata = serde_json::from_str(&contents)?;
+    for member in metadata.members.iter().flatten() {
+        directory::check_member_path(&member.path)?;
+    }
     return Ok(metadata);
 }
 
@@ -75,7 +115,7 @@ pub fn get_user_helper(path: &PathBuf) -> Result<String> {
     Ok(path
         .owner()?
         .name()?
-        .ok_or_else(|| format!("owner not found"))?
+        .ok_or_else(|| String::from("owner not found"))?
     )
 }
 
@@ -134,6 +174,26 @@ pub fn check_if_dir(local_path: &PathBuf) -> std::result::Result<(), FileError>
     }
 }
 
+// symlinks and hardlinks, e.g. to objects in storage, are removed so a new version can't be written through them
+pub fn remove_link(local_path: &PathBuf) -> std::result::Result<(), FileError> {
+    let is_link = match local_path.symlink_metadata() {
+        Ok(metadata) => metadata.file_type().is_symlink() || (metadata.is_file() && metadata.nlink() > 1),
+        Err(_) => false,
+    };
+    if !is_link {
+        return Ok(())
+    }
+    fs::remove_file(local_path).map_err(|e|
+        FileError{
+            relative_path: try_to_get_rel_path(local_path),
+            absolute_path: try_to_get_abs_path(local_path),
+            error: FileErrorType::FileNotCopied,
+            error_message: Some(format!("could not remove link {}: {e}", local_path.display())),
+            input: local_path.clone()
+        }
+    )
+}
+
 pub fn get_file_size(local_path: &PathBuf) -> std::result::Result<u64, FileError> {
     Ok(local_path.metadata().map_err(|e|
             FileError{
@@ -146,6 +206,52 @@ pub fn get_file_size(local_path: &PathBuf) -> std::result::Result<u64, FileError
         )?.len())
 }
 
+// modification time to the nanosecond and permission bits, as recorded in .dvs files
+pub fn get_file_attributes(local_path: &PathBuf) -> std::result::Result<(String, String), FileError> {
+    let attributes = || -> Result<(String, String)> {
+        let metadata = local_path.metadata()?;
+        let mtime = DateTime::<Utc>::from(metadata.modified()?).to_rfc3339_opts(SecondsFormat::Nanos, true);
+        // only the permission bits: setuid, setgid and sticky bits aren't carried between machines
+        Ok((mtime, format!("{:o}", metadata.permissions().mode() & 0o777)))
+    };
+    attributes().map_err(|e|
+        FileError{
+            relative_path: try_to_get_rel_path(local_path),
+            absolute_path: try_to_get_abs_path(local_path),
+            error: FileErrorType::AttributesNotFound,
+            error_message: Some(e.to_string()),
+            input: local_path.clone()
+        }
+    )
+}
+
+// gives a copied file the mode recorded in its metadata and, with restore_mtime, the recorded modification time;
+// attributes that weren't recorded are left as the copy made them
+pub fn restore_file_attributes(local_path: &PathBuf, metadata: &Metadata, restore_mtime: bool) -> std::result::Result<(), FileError> {
+    let restore = || -> Result<()> {
+        if let Some(mtime) = metadata.mtime.as_ref().filter(|_| restore_mtime) {
+            let mtime = SystemTime::from(DateTime::parse_from_rfc3339(mtime).map_err(|e| format!("mtime {mtime} invalid: {e}"))?);
+            File::open(local_path)?.set_modified(mtime)?;
+        }
+        // after the modification time, since the file is opened to set it and the mode may not let it be read
+        if let Some(mode) = &metadata.mode {
+            // a .dvs file written by hand or by another tool mustn't make the file setuid
+            let mode = u32::from_str_radix(mode, 8).map_err(|e| format!("mode {mode} invalid: {e}"))? & 0o777;
+            fs::set_permissions(local_path, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    };
+    restore().map_err(|e|
+        FileError{
+            relative_path: try_to_get_rel_path(local_path),
+            absolute_path: try_to_get_abs_path(local_path),
+            error: FileErrorType::AttributesNotRestored,
+            error_message: Some(e.to_string()),
+            input: local_path.clone()
+        }
+    )
+}
+
 pub fn check_meta_files_exist(queued_paths: &Vec<PathBuf>) -> std::result::Result<(), BatchError> {
     // Find the first path that does not have a corresponding .dvs file
     if let Some(path) = queued_paths
@@ -1,7 +1,37 @@
-use std::{fs::File, path::PathBuf, io::{self, Read, Result}};
+use std::{collections::BTreeMap, fs::File, path::PathBuf, io::{self, Read, Result}};
 use crate::helpers::cache;
 use blake3::Hasher;
-use crate::helpers::{error::{FileError, FileErrorType}, file};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::helpers::{error::{FileError, FileErrorType}, file, progress::{self, Stage}};
+
+// hashed a piece at a time so progress can be reported and the hash cancelled in between
+const HASH_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+// digests that can be recorded in .dvs files alongside the blake3 hash, which storage is always addressed by
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone, Copy, PartialOrd, Ord, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DigestAlgorithm {
+    Blake3,
+    Sha256,
+    Md5,
+}
+
+impl DigestAlgorithm {
+    pub fn algorithm_to_string(&self) -> String {
+        match self {
+            DigestAlgorithm::Blake3 => String::from("blake3"),
+            DigestAlgorithm::Sha256 => String::from("sha256"),
+            DigestAlgorithm::Md5 => String::from("md5"),
+        }
+    }
+}
+
+enum Digester {
+    Blake3(Hasher),
+    Sha256(Sha256),
+    Md5(md5::Context),
+}
 
 
 pub fn hash_file_with_blake3(file_path: &PathBuf) -> io::Result<Option<String>> {
@@ -16,7 +46,11 @@ pub fn hash_file_with_blake3(file_path: &PathBuf) -> io::Result<Option<String>>
         Err(e) => return Err(e),
     };
     let mut hasher = Hasher::new();
-    hasher.update_rayon(&mmap);
+    let stage = progress::begin(Stage::Hashing, Some(mmap.len() as u64));
+    for chunk in mmap.chunks(HASH_CHUNK_SIZE) {
+        hasher.update_rayon(chunk);
+        stage.advance(chunk.len() as u64)?;
+    }
     Ok(Some(hasher.finalize().to_string()))
 }
 
@@ -25,6 +59,7 @@ fn hash_file_with_blake3_direct(file_path: &PathBuf) -> io::Result<Option<String
 
     let mut hasher = Hasher::new();
     let mut buffer = [0u8; 16384]; // 16 KB buffer size
+    let stage = progress::begin(Stage::Hashing, file.metadata().ok().map(|metadata| metadata.len()));
 
     loop {
         let bytes_read = file.read(&mut buffer)?;
@@ -32,6 +67,7 @@ fn hash_file_with_blake3_direct(file_path: &PathBuf) -> io::Result<Option<String
             break;
         }
         hasher.update(&buffer[..bytes_read]);
+        stage.advance(bytes_read as u64)?;
     }
 
     let hash_result = hasher.finalize();
@@ -71,6 +107,58 @@ fn maybe_memmap_file(file: &File) -> Result<Option<memmap2::Mmap>> {
 }
 
 
+// hex digests of a file with each of the algorithms, keyed by algorithm name; the file is read once
+pub fn digest_file(file_path: &PathBuf, algorithms: &[DigestAlgorithm]) -> io::Result<BTreeMap<String, String>> {
+    let file = File::open(file_path)?;
+    let size = file.metadata().ok().map(|metadata| metadata.len());
+    digest_reader(file, size, algorithms)
+}
+
+// digest_file for any reader, e.g. an object as it's read out of storage; size, if known, is reported as progress
+pub fn digest_reader(mut reader: impl Read, size: Option<u64>, algorithms: &[DigestAlgorithm]) -> io::Result<BTreeMap<String, String>> {
+    let mut digesters: Vec<(DigestAlgorithm, Digester)> = algorithms.iter().map(|algorithm| (*algorithm, match algorithm {
+        DigestAlgorithm::Blake3 => Digester::Blake3(Hasher::new()),
+        DigestAlgorithm::Sha256 => Digester::Sha256(Sha256::new()),
+        DigestAlgorithm::Md5 => Digester::Md5(md5::Context::new()),
+    })).collect();
+    let mut buffer = vec![0u8; 1024 * 1024];
+    let stage = progress::begin(Stage::Hashing, size);
+
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        for (_, digester) in digesters.iter_mut() {
+            match digester {
+                Digester::Blake3(hasher) => {hasher.update(&buffer[..bytes_read]);}
+                Digester::Sha256(hasher) => hasher.update(&buffer[..bytes_read]),
+                Digester::Md5(context) => context.consume(&buffer[..bytes_read]),
+            }
+        }
+        stage.advance(bytes_read as u64)?;
+    }
+
+    Ok(digesters.into_iter().map(|(algorithm, digester)| (algorithm.algorithm_to_string(), match digester {
+        Digester::Blake3(hasher) => hasher.finalize().to_string(),
+        Digester::Sha256(hasher) => hex::encode(hasher.finalize()),
+        Digester::Md5(context) => format!("{:x}", context.compute()),
+    })).collect())
+}
+
+// digests recorded by dvs_add besides the blake3 hash
+pub fn get_file_digests(local_path: &PathBuf, algorithms: &[DigestAlgorithm]) -> std::result::Result<BTreeMap<String, String>, FileError> {
+    digest_file(local_path, algorithms).map_err(|e|
+        FileError{
+            relative_path: file::try_to_get_rel_path(local_path),
+            absolute_path: file::try_to_get_abs_path(local_path),
+            error: FileErrorType::HashNotFound,
+            error_message: Some(e.to_string()),
+            input: local_path.clone()
+        }
+    )
+}
+
 pub fn get_file_hash(local_path: &PathBuf) -> std::result::Result<String, FileError> {
     // get cache if possible
     if let Ok(cached_hash) = cache::get_cached_hash(local_path) {
@@ -105,11 +193,3 @@ pub fn get_file_hash(local_path: &PathBuf) -> std::result::Result<String, FileEr
         },
     }
 }
-
-
-pub fn get_storage_path(storage_dir: &PathBuf, file_hash: &String) -> PathBuf {
-    let first_hash_segment: &str = &file_hash[..2];
-    let second_hash_segment: &str = &file_hash[2..];
-    return storage_dir.join(first_hash_segment).join(second_hash_segment);
-
-}
\ No newline at end of file
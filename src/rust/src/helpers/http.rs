@@ -0,0 +1,151 @@
+use std::{env, fs::File, io::Read, path::PathBuf, time::SystemTime};
+use chrono::DateTime;
+use crate::helpers::{config::HttpConfig, copy, layout, outcome::Outcome, progress, storage::{ObjectInfo, StorageBackend}};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// bearer token sent to a `dvs_serve` server, if it requires one for uploads
+pub const TOKEN_VAR: &str = "DVS_SERVER_TOKEN";
+
+// a storage directory served over http(s), laid out as its <url>/.dvs_layout.yaml says;
+// writable only when served by `dvs_serve`, which accepts uploads with PUT
+pub struct HttpStorage {
+    url: String,
+    layout: layout::Layout,
+    writable: bool,
+    token: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl HttpStorage {
+    pub fn new(conf: &HttpConfig, writable: bool) -> Result<HttpStorage> {
+        if !conf.url.starts_with("http://") && !conf.url.starts_with("https://") {
+            return Err(format!("url: {} in dvs.yaml must start with http:// or https://", conf.url).into());
+        }
+        let url = conf.url.trim_end_matches('/').to_string();
+        let agent = ureq::AgentBuilder::new().build();
+        let layout = read_layout(&agent, &url)?;
+        Ok(HttpStorage{
+            url,
+            layout,
+            writable,
+            token: env::var(TOKEN_VAR).ok(),
+            agent,
+        })
+    }
+
+    // as layout::Layout::storage_path, checking for objects already moved on the server
+    fn storage_path(&self, hash: &String) -> String {
+        let path = |depth| layout::sharded_path(&PathBuf::new(), hash, depth).to_string_lossy().to_string();
+        let current_path = path(self.layout.sharding_depth);
+        if let Some(depth) = self.layout.migrating_to {
+            let migrated_path = path(depth);
+            let found = |path: &String| self.agent.head(&format!("{}/{path}", self.url)).call().is_ok();
+            if found(&migrated_path) || !found(&current_path) {
+                return migrated_path
+            }
+        }
+        current_path
+    }
+
+    fn request(&self, method: &str, hash: &String) -> ureq::Request {
+        let request = self.agent.request(method, &self.location(hash));
+        match &self.token {
+            Some(token) => request.set("authorization", &format!("Bearer {token}")),
+            None => request,
+        }
+    }
+
+    fn head(&self, hash: &String) -> Result<Option<ureq::Response>> {
+        match self.request("HEAD", hash).call() {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(format!("HEAD {} failed: {e}", self.location(hash)).into()),
+        }
+    }
+}
+
+impl StorageBackend for HttpStorage {
+    fn exists(&self, hash: &String) -> Result<bool> {
+        Ok(self.head(hash)?.is_some())
+    }
+
+    fn put(&self, local_path: &PathBuf, hash: &String) -> Result<Outcome> {
+        if !self.writable {
+            return Err(format!("could not copy to {}: http storage is read-only", self.location(hash)).into());
+        }
+
+        let file = File::open(local_path).map_err(|e|
+            format!("could not open source file: {}\n{e}", local_path.display())
+        )?;
+        let size = file.metadata()?.len();
+
+        // the server checks the upload against the hash before storing it, and answers 200 if it already had it
+        let response = self.request("PUT", hash)
+            .set("content-length", &size.to_string())
+            .send(progress::Reader::new(file, Some(size)))
+            .map_err(|e| match e {
+                ureq::Error::Status(code, response) => format!(
+                    "PUT {} failed with status {code}: {}", self.location(hash), response.into_string().unwrap_or_default()
+                ),
+                e => format!("PUT {} failed: {e}", self.location(hash)),
+            })?;
+        match response.status() {
+            201 => Ok(Outcome::Copied),
+            _ => Ok(Outcome::Present),
+        }
+    }
+
+    fn get(&self, hash: &String, local_path: &PathBuf) -> Result<()> {
+        // only replace the local file if the download matches the hash in its metadata
+        copy::write_verified(&mut self.open(hash)?, hash, local_path)
+    }
+
+    fn open(&self, hash: &String) -> Result<Box<dyn Read + '_>> {
+        let response = self.request("GET", hash).call().map_err(|e|
+            format!("GET {} failed: {e}", self.location(hash))
+        )?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    fn delete(&self, hash: &String) -> Result<()> {
+        Err(format!("could not remove {}: http storage is read-only", self.location(hash)).into())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Err(format!("could not list {}: http storage can't be listed", self.url).into())
+    }
+
+    fn stat(&self, hash: &String) -> Result<ObjectInfo> {
+        let response = self.head(hash)?
+            .ok_or_else(|| format!("{} not found", self.location(hash)))?;
+
+        let size = response
+            .header("content-length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .ok_or_else(|| format!("could not get size of {}", self.location(hash)))?;
+
+        let modification_time = response
+            .header("last-modified")
+            .and_then(|time| DateTime::parse_from_rfc2822(time).ok())
+            .map(SystemTime::from);
+
+        Ok(ObjectInfo{size, modification_time})
+    }
+
+    fn location(&self, hash: &String) -> String {
+        format!("{}/{}", self.url, self.storage_path(hash))
+    }
+}
+
+// the layout in <url>/.dvs_layout.yaml, or the default if the server has none
+fn read_layout(agent: &ureq::Agent, url: &String) -> Result<layout::Layout> {
+    let layout_url = format!("{url}/{}", layout::LAYOUT_FILE);
+    let contents = match agent.get(&layout_url).call() {
+        Ok(response) => response.into_string().map_err(|e| format!("could not read {layout_url}: {e}"))?,
+        Err(ureq::Error::Status(404, _)) => return Ok(layout::Layout::default()),
+        Err(e) => return Err(format!("GET {layout_url} failed: {e}").into()),
+    };
+    Ok(layout::parse(&contents, &layout_url)?)
+}
@@ -1,4 +1,4 @@
-use std::{fs::{File, OpenOptions}, path::PathBuf};
+use std::{fs::{File, OpenOptions}, path::PathBuf, sync::Mutex};
 use crate::helpers::repo;
 use std::io::prelude::*;
 use crate::helpers::{error::{FileError, FileErrorType}, file};
@@ -6,6 +6,9 @@ use crate::helpers::{error::{FileError, FileErrorType}, file};
 pub type Result<T> = core::result::Result<T, Error>;
 pub type Error = Box<dyn std::error::Error>;
 
+// files in the same directory are added in parallel, so only one thread updates a .gitignore at a time
+static GITIGNORE_LOCK: Mutex<()> = Mutex::new(());
+
 pub fn add_gitignore_entry_helper(path: &PathBuf) -> Result<()> {
     let abs_path = path.canonicalize()?;
 
@@ -18,6 +21,8 @@ pub fn add_gitignore_entry_helper(path: &PathBuf) -> Result<()> {
     let ignore_entry1 = format!("/{}", repo::get_relative_path(&dir, path)?.display());
     let ignore_entry2 = format!("!/{}.dvs", repo::get_relative_path(&dir, path)?.display());
 
+    let _guard = GITIGNORE_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
     // open the gitignore file, creating one if it doesn't exist
     let ignore_file = dir.join(".gitignore");
     if !ignore_file.exists() {
@@ -43,7 +48,7 @@ pub fn add_gitignore_entry(local_path: &PathBuf) -> std::result::Result<(), File
     add_gitignore_entry_helper(local_path).map_err(|e| {
         let err_mess = match local_path.parent() {
             Some(parent) => format!("could not create entry for {}/.gitignore", parent.display()),
-            None => format!("could not create entry for .gitignore"),
+            None => String::from("could not create entry for .gitignore"),
         };
         FileError{
             relative_path: file::try_to_get_rel_path(local_path),
@@ -0,0 +1,104 @@
+use std::{fs::{self, OpenOptions}, io::Write, os::unix::fs::PermissionsExt, path::PathBuf};
+use file_owner::Group;
+use serde::{Serialize, Deserialize};
+use crate::helpers::{config::{Backend, Config}, copy, storage};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// hidden, so it isn't mistaken for objects
+pub const INDEX_DIR: &str = ".dvs_index";
+pub const INDEX_SUFFIX: &str = ".jsonl";
+
+// a file version as it was added, so an object can be traced back to the projects and paths it came from
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct IndexEntry {
+    pub blake3_checksum: String,
+    // see repo::repo_identity
+    pub repo: String,
+    // relative to the root of the repository
+    pub relative_path: PathBuf,
+    pub saved_by: String,
+    pub add_time: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+// the reverse index of a local storage directory: <storage_dir>/.dvs_index/<hash[..2]>.jsonl,
+// one entry per line; entries are only ever appended
+pub struct Index {
+    pub storage_dir: PathBuf,
+    pub permissions: u32,
+    pub group: Option<Group>,
+}
+
+impl Index {
+    // only local storage directories keep an index; None for other backends
+    pub fn from_config(conf: &Config) -> Option<Index> {
+        if conf.backend != Backend::Local {
+            return None
+        }
+        let storage = storage::local_from_config(conf).ok()?;
+        Some(Index{storage_dir: storage.storage_dir, permissions: storage.permissions, group: storage.group})
+    }
+
+    pub fn index_path(&self, hash: &str) -> PathBuf {
+        index_path(&self.storage_dir, hash)
+    }
+
+    // each entry is a single write to a file opened for appending, so adds running at once don't interleave their lines
+    pub fn record(&self, entry: &IndexEntry) -> Result<()> {
+        let index_path = self.index_path(&entry.blake3_checksum);
+        fs::create_dir_all(self.storage_dir.join(INDEX_DIR))?;
+
+        let created = !index_path.exists();
+        let mut file = OpenOptions::new().create(true).append(true).open(&index_path)?;
+        if created {
+            // other users append to it too, so it's writable by the group even if objects aren't
+            let _ = fs::set_permissions(&index_path, fs::Permissions::from_mode(self.permissions | 0o660));
+            let _ = copy::set_group(&self.group, &index_path);
+        }
+
+        let line = format!("{}\n", serde_json::to_string(entry)?);
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+pub fn index_path(storage_dir: &PathBuf, hash: &str) -> PathBuf {
+    storage_dir.join(INDEX_DIR).join(format!("{}{INDEX_SUFFIX}", &hash[..2]))
+}
+
+// entries for hashes starting with hash_prefix, of at least 2 characters, or all entries; oldest first.
+// lines that don't parse, e.g. from an add that was killed as it wrote, are skipped
+pub fn read(storage_dir: &PathBuf, hash_prefix: Option<&str>) -> Result<Vec<IndexEntry>> {
+    let index_paths: Vec<PathBuf> = match hash_prefix {
+        Some(prefix) => vec![index_path(storage_dir, prefix)],
+        None => match fs::read_dir(storage_dir.join(INDEX_DIR)) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.to_string_lossy().ends_with(INDEX_SUFFIX))
+                .collect(),
+            // nothing was added since the index was introduced
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        },
+    };
+
+    let mut entries: Vec<IndexEntry> = Vec::new();
+    for index_path in index_paths {
+        let contents = match fs::read_to_string(&index_path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(format!("could not read {}: {e}", index_path.display()).into()),
+        };
+        entries.extend(contents
+            .lines()
+            .filter_map(|line| serde_json::from_str::<IndexEntry>(line).ok())
+            .filter(|entry| hash_prefix.map_or(true, |prefix| entry.blake3_checksum.starts_with(prefix))));
+    }
+    // add_time is RFC 3339 in UTC, so it sorts as text
+    entries.sort_by(|a, b| a.add_time.cmp(&b.add_time));
+    Ok(entries)
+}
@@ -0,0 +1,114 @@
+use crate::helpers::error::{BatchError, BatchErrorType};
+use serde::{Serialize, Deserialize};
+use std::{fs, path::PathBuf};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// hidden, so it isn't mistaken for an object
+pub const LAYOUT_FILE: &str = ".dvs_layout.yaml";
+pub const LAYOUT_VERSION: u32 = 1;
+pub const MAX_SHARDING_DEPTH: u32 = 3;
+
+// how objects are laid out in a local storage directory:
+// <storage_dir>/<hash[..2]>/.../<rest of hash> with sharding_depth 2-character directories
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Layout {
+    pub layout_version: u32,
+    pub hash_algorithm: String,
+    pub sharding_depth: u32,
+    // set while a migration to another sharding depth is under way, so it can be resumed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub migrating_to: Option<u32>,
+}
+
+// storage directories without a layout file were all written as <hash[..2]>/<hash[2..]>
+impl Default for Layout {
+    fn default() -> Self {
+        Layout{
+            layout_version: LAYOUT_VERSION,
+            hash_algorithm: String::from("blake3"),
+            sharding_depth: 1,
+            migrating_to: None,
+        }
+    }
+}
+
+impl Layout {
+    // where the object with the given storage key is; mid-migration, objects already moved
+    // are at their new path and new objects are written there
+    pub fn storage_path(&self, storage_dir: &PathBuf, key: &String) -> PathBuf {
+        if let Some(depth) = self.migrating_to {
+            let migrated_path = sharded_path(storage_dir, key, depth);
+            if migrated_path.exists() || !sharded_path(storage_dir, key, self.sharding_depth).exists() {
+                return migrated_path
+            }
+        }
+        sharded_path(storage_dir, key, self.sharding_depth)
+    }
+}
+
+pub fn sharded_path(storage_dir: &PathBuf, key: &String, depth: u32) -> PathBuf {
+    let mut storage_path = storage_dir.clone();
+    let mut rest: &str = key;
+    for _ in 0..depth {
+        storage_path.push(&rest[..2]);
+        rest = &rest[2..];
+    }
+    storage_path.join(rest)
+}
+
+pub fn read(storage_dir: &PathBuf) -> std::result::Result<Layout, BatchError> {
+    let layout_path = storage_dir.join(LAYOUT_FILE);
+    if !layout_path.exists() {
+        return Ok(Layout::default())
+    }
+
+    let contents = fs::read_to_string(&layout_path).map_err(|e|
+        layout_error(format!("could not read {}: {e}", layout_path.display()))
+    )?;
+    parse(&contents, &layout_path.display().to_string())
+}
+
+// the contents of a layout file, checked; location is where they were read from, for messages
+pub fn parse(contents: &str, location: &String) -> std::result::Result<Layout, BatchError> {
+    let layout: Layout = serde_yaml::from_str(contents).map_err(|e|
+        layout_error(format!("could not read {location}: {e}"))
+    )?;
+
+    // written by a newer version of dvs
+    if layout.layout_version > LAYOUT_VERSION {
+        return Err(layout_error(format!("{location} has layout_version {}, this version of dvs reads up to {LAYOUT_VERSION}; update dvs", layout.layout_version)))
+    }
+    if layout.hash_algorithm != "blake3" {
+        return Err(layout_error(format!("{location} has hash_algorithm {}, only blake3 is supported", layout.hash_algorithm)))
+    }
+    check_sharding_depth(layout.sharding_depth)?;
+    if let Some(depth) = layout.migrating_to {
+        check_sharding_depth(depth)?;
+    }
+    Ok(layout)
+}
+
+// written to a temporary file first, so the layout file is never half written
+pub fn write(storage_dir: &PathBuf, layout: &Layout) -> Result<()> {
+    let layout_path = storage_dir.join(LAYOUT_FILE);
+    let temp_path = storage_dir.join(format!("{LAYOUT_FILE}.tmp"));
+    fs::write(&temp_path, serde_yaml::to_string(layout)?)?;
+    fs::rename(&temp_path, &layout_path)?;
+    Ok(())
+}
+
+pub fn check_sharding_depth(depth: u32) -> std::result::Result<(), BatchError> {
+    if depth > MAX_SHARDING_DEPTH {
+        return Err(layout_error(format!("sharding depth {depth} invalid, must be between 0 and {MAX_SHARDING_DEPTH}")))
+    }
+    Ok(())
+}
+
+fn layout_error(message: String) -> BatchError {
+    BatchError{
+        error: BatchErrorType::LayoutInvalid,
+        error_message: message
+    }
+}
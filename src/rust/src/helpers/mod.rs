@@ -7,4 +7,17 @@ pub mod repo;
 pub mod parse;
 pub mod cache;
 pub mod error;
-pub mod outcome;
\ No newline at end of file
+pub mod outcome;
+pub mod storage;
+pub mod compress;
+pub mod encrypt;
+pub mod chunk;
+pub mod s3;
+pub mod sftp;
+pub mod http;
+pub mod layout;
+pub mod index;
+pub mod annotation;
+pub mod batch;
+pub mod progress;
+pub mod directory;
@@ -4,7 +4,11 @@
 #[derive(Clone, PartialEq, Debug)]
 pub enum Outcome {
     Copied,
+    // hardlinked or symlinked to its object in storage
+    Linked,
     Present,
+    // copied, but encrypted with a key that isn't available, so only its header was checked
+    Unchecked,
     Error,
 }
 
@@ -14,14 +18,43 @@ pub enum Status {
     Absent,
     Unsynced,
     Current,
+    // in a tracked directory but not in the version recorded for it
+    Untracked,
     Error,
 }
 
+// result of checking an object in storage
+#[derive(Clone, PartialEq, Debug)]
+pub enum Integrity {
+    Ok,
+    // contents don't hash to the object's name
+    Corrupt,
+    // shorter than the file or chunk it was stored for
+    Truncated,
+    // intact contents of another object, stored under the wrong name
+    Misnamed,
+    // not an object: not named like one
+    Stray,
+    // referenced by a .dvs file but not in storage
+    Missing,
+    // encrypted with a key that isn't available, so its contents can't be checked
+    Unchecked,
+}
+
+// result of checking a file or storage object against a digest recorded in its .dvs file
+#[derive(Clone, PartialEq, Debug)]
+pub enum Verification {
+    Match,
+    Mismatch,
+}
+
 impl Outcome {
     pub fn outcome_to_string(&self) -> String {
         match self {
             Outcome::Copied => String::from("copied"),
+            Outcome::Linked => String::from("linked"),
             Outcome::Present => String::from("present"),
+            Outcome::Unchecked => String::from("unchecked"),
             Outcome::Error => String::from("error")
         }
     }
@@ -33,8 +66,32 @@ impl Status {
             Status::Absent => String::from("absent"),
             Status::Unsynced => String::from("unsynced"),
             Status::Current => String::from("current"),
+            Status::Untracked => String::from("untracked"),
             Status::Error => String::from("error"),
         }
     }
 }
 
+
+impl Integrity {
+    pub fn outcome_to_string(&self) -> String {
+        match self {
+            Integrity::Ok => String::from("ok"),
+            Integrity::Corrupt => String::from("corrupt"),
+            Integrity::Truncated => String::from("truncated"),
+            Integrity::Misnamed => String::from("misnamed"),
+            Integrity::Stray => String::from("stray"),
+            Integrity::Missing => String::from("missing"),
+            Integrity::Unchecked => String::from("unchecked"),
+        }
+    }
+}
+
+impl Verification {
+    pub fn outcome_to_string(&self) -> String {
+        match self {
+            Verification::Match => String::from("match"),
+            Verification::Mismatch => String::from("mismatch"),
+        }
+    }
+}
@@ -6,7 +6,12 @@ use crate::helpers::{file, repo, error::{BatchError, BatchErrorType}};
 
 pub fn get_all_meta_files() -> Result<Vec<PathBuf>, BatchError> {
     let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
-    Ok(WalkDir::new(&git_dir)
+    Ok(get_meta_files_in(&git_dir))
+}
+
+// files with a .dvs metadata file anywhere under dir, without the .dvs extension
+pub fn get_meta_files_in(dir: &PathBuf) -> Vec<PathBuf> {
+    WalkDir::new(dir)
         .into_iter()
         .filter_map(|e| e.ok())
         .filter(|e| e.path().extension().map_or(false, |ext| ext == "dvs"))
@@ -15,7 +20,6 @@ pub fn get_all_meta_files() -> Result<Vec<PathBuf>, BatchError> {
             PathBuf::from(string)
         })
         .collect()
-    )
 }
 
 pub fn parse_files_from_globs_add(globs: &Vec<String>) -> Vec<PathBuf> {
@@ -0,0 +1,265 @@
+use std::{cell::RefCell, fmt, io::{self, Read}, path::{Path, PathBuf}, sync::{Arc, atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering}}};
+
+// what is happening to the file a progress update is about
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stage {
+    Started,
+    Hashing,
+    Copying,
+    Done,
+}
+
+impl Stage {
+    pub fn stage_to_string(&self) -> String {
+        match self {
+            Stage::Started => String::from("started"),
+            Stage::Hashing => String::from("hashing"),
+            Stage::Copying => String::from("copying"),
+            Stage::Done => String::from("done"),
+        }
+    }
+}
+
+// passed to the progress callback as the files of a batch are hashed and copied
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProgressUpdate {
+    pub files_done: usize,
+    pub files_total: usize,
+    // position of the file this update is about in the batch, from 1
+    pub file_index: usize,
+    pub path: PathBuf,
+    pub stage: Stage,
+    // bytes of the file hashed or copied so far in this stage, out of size if it's known
+    pub bytes: u64,
+    pub size: Option<u64>,
+    // bytes hashed and copied for the whole batch so far
+    pub bytes_hashed: u64,
+    pub bytes_copied: u64,
+}
+
+// progress of a batch of files, shared by its workers; the callback is called from whichever
+// worker made progress, and the batch is cancelled once it returns false
+pub struct Progress {
+    callback: Box<dyn Fn(&ProgressUpdate) -> bool + Send + Sync>,
+    files_total: AtomicUsize,
+    files_done: AtomicUsize,
+    bytes_hashed: AtomicU64,
+    bytes_copied: AtomicU64,
+    cancelled: AtomicBool,
+}
+
+impl Progress {
+    pub fn new(callback: impl Fn(&ProgressUpdate) -> bool + Send + Sync + 'static) -> Progress {
+        Progress{
+            callback: Box::new(callback),
+            files_total: AtomicUsize::new(0),
+            files_done: AtomicUsize::new(0),
+            bytes_hashed: AtomicU64::new(0),
+            bytes_copied: AtomicU64::new(0),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    pub fn start_batch(&self, files_total: usize) {
+        self.files_total.store(files_total, Ordering::Relaxed);
+    }
+
+    fn report(&self, current: &Current) {
+        let (stage, bytes, size) = current.stage.unwrap_or((current.status, 0, current.size));
+        let update = ProgressUpdate{
+            files_done: self.files_done.load(Ordering::Relaxed),
+            files_total: self.files_total.load(Ordering::Relaxed),
+            file_index: current.file_index + 1,
+            path: current.path.clone(),
+            stage,
+            bytes,
+            size,
+            bytes_hashed: self.bytes_hashed.load(Ordering::Relaxed),
+            bytes_copied: self.bytes_copied.load(Ordering::Relaxed),
+        };
+        if !(self.callback)(&update) {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+// returned by hashing and copying loops once the batch they're part of is cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+// the file a worker thread is on
+struct Current {
+    progress: Arc<Progress>,
+    file_index: usize,
+    path: PathBuf,
+    status: Stage,
+    // expected size of the file, e.g. from its metadata, for stages that can't tell
+    size: Option<u64>,
+    // stage in progress, bytes through it, and its size
+    stage: Option<(Stage, u64, Option<u64>)>,
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+// run f for the file at file_index of a batch, reporting to progress while it's hashed and copied.
+// a worker waiting on rayon can pick up another file on the same thread, so the file it was on is put back after
+pub fn for_file<T>(progress: &Arc<Progress>, file_index: usize, path: &Path, f: impl FnOnce() -> T) -> T {
+    let current = Current{
+        progress: progress.clone(),
+        file_index,
+        path: path.to_path_buf(),
+        status: Stage::Started,
+        size: None,
+        stage: None,
+    };
+    progress.report(&current);
+    let previous = CURRENT.with(|cell| cell.replace(Some(current)));
+
+    let result = f();
+
+    let current = CURRENT.with(|cell| cell.replace(previous));
+    progress.files_done.fetch_add(1, Ordering::Relaxed);
+    if let Some(mut current) = current {
+        current.status = Stage::Done;
+        current.stage = None;
+        progress.report(&current);
+    }
+    result
+}
+
+// the file a worker is on, for work done for it on other threads
+#[derive(Clone)]
+pub struct FileScope {
+    progress: Arc<Progress>,
+    file_index: usize,
+    path: PathBuf,
+    size: Option<u64>,
+}
+
+pub fn file_scope() -> Option<FileScope> {
+    CURRENT.with(|cell| cell.borrow().as_ref().map(|current| FileScope{
+        progress: current.progress.clone(),
+        file_index: current.file_index,
+        path: current.path.clone(),
+        size: current.size,
+    }))
+}
+
+// run f on this thread as part of the file in scope, reporting its stages and stopping once the batch is cancelled
+// like the file's own worker; unlike for_file, it isn't counted as a file. the thread may be waiting on another file,
+// which is put back after
+pub fn in_scope<T>(scope: &Option<FileScope>, f: impl FnOnce() -> T) -> T {
+    let current = scope.as_ref().map(|scope| Current{
+        progress: scope.progress.clone(),
+        file_index: scope.file_index,
+        path: scope.path.clone(),
+        status: Stage::Started,
+        size: scope.size,
+        stage: None,
+    });
+    let previous = CURRENT.with(|cell| cell.replace(current));
+    let result = f();
+    CURRENT.with(|cell| cell.replace(previous));
+    result
+}
+
+// the expected size of the current file, for copies from streams of unknown length
+pub fn set_size(size: u64) {
+    CURRENT.with(|cell| {
+        if let Some(current) = cell.borrow_mut().as_mut() {
+            current.size = Some(size);
+        }
+    });
+}
+
+// true if the batch the current file is part of was cancelled
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|cell| cell.borrow().as_ref().is_some_and(|current| current.progress.is_cancelled()))
+}
+
+// a hashing or copying pass over the current file; only the outermost one is reported,
+// e.g. copying a whole chunked file rather than each of its chunks
+pub struct StageGuard {
+    active: bool,
+}
+
+pub fn begin(stage: Stage, size: Option<u64>) -> StageGuard {
+    let active = CURRENT.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(current) if current.stage.is_none() => {
+            current.stage = Some((stage, 0, size.or(current.size)));
+            true
+        }
+        _ => false,
+    });
+    StageGuard{active}
+}
+
+impl StageGuard {
+    // count bytes through the stage; errors with Cancelled once the batch is cancelled,
+    // so a copy stops between chunks and cleans up after itself
+    pub fn advance(&self, bytes: u64) -> io::Result<()> {
+        let progress = CURRENT.with(|cell| {
+            let mut cell = cell.borrow_mut();
+            let current = cell.as_mut()?;
+            if self.active {
+                if let Some((stage, done, _)) = current.stage.as_mut() {
+                    *done += bytes;
+                    let total = if *stage == Stage::Hashing {&current.progress.bytes_hashed} else {&current.progress.bytes_copied};
+                    total.fetch_add(bytes, Ordering::Relaxed);
+                }
+                current.progress.report(current);
+            }
+            Some(current.progress.is_cancelled())
+        });
+        match progress {
+            Some(true) => Err(io::Error::other(Cancelled)),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Drop for StageGuard {
+    fn drop(&mut self) {
+        if self.active {
+            CURRENT.with(|cell| {
+                if let Some(current) = cell.borrow_mut().as_mut() {
+                    current.stage = None;
+                }
+            });
+        }
+    }
+}
+
+// counts what's read through it as a copying stage of the current file
+pub struct Reader<R: Read> {
+    inner: R,
+    stage: StageGuard,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R, size: Option<u64>) -> Reader<R> {
+        Reader{inner, stage: begin(Stage::Copying, size)}
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_read = self.inner.read(buf)?;
+        self.stage.advance(bytes_read as u64)?;
+        Ok(bytes_read)
+    }
+}
@@ -1,7 +1,9 @@
 use std::path::{PathBuf, Path};
 use std::fs;
+use std::io::Write;
+use std::process::{Command, Stdio};
 use path_absolutize::Absolutize;
-use crate::helpers::{file, error::{BatchError, BatchErrorType, FileError, FileErrorType}};
+use crate::helpers::{file, layout, error::{BatchError, BatchErrorType, FileError, FileErrorType}};
 
 pub type Result<T> = core::result::Result<T, Error>;
 pub type Error = Box<dyn std::error::Error>;
@@ -38,9 +40,10 @@ fn is_git_repo(dir: &PathBuf) -> bool {
     dir.join(".git").is_dir()
 }
 
+// the storage layout file doesn't count, since dvs_init writes it into empty storage directories
 pub fn is_directory_empty(directory: &Path) -> Result<bool> {
     let mut entries = fs::read_dir(directory)?;
-    Ok(entries.next().is_none())
+    Ok(entries.all(|entry| entry.is_ok_and(|entry| entry.file_name() == layout::LAYOUT_FILE)))
 }
 
 fn nearest_repo_error(dir: &PathBuf) -> BatchError {
@@ -114,4 +117,80 @@ pub fn dir_in_git_repo(path: &PathBuf, git_dir: &PathBuf) -> bool {
     } else {
         false
     }
-}
\ No newline at end of file
+}
+
+// contents of every .dvs file in every commit reachable from a branch or tag
+pub fn metadata_in_history(repo_dir: &PathBuf) -> Result<Vec<String>> {
+    let objects = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(["rev-list", "--all", "--objects"])
+        .output()
+        .map_err(|e| format!("could not run git in {}: {e}", repo_dir.display()))?;
+    if !objects.status.success() {
+        return Err(format!("git rev-list failed in {}: {}", repo_dir.display(), String::from_utf8_lossy(&objects.stderr)).into())
+    }
+
+    // <object id> <path>, for every blob and tree in history
+    let stdout = String::from_utf8_lossy(&objects.stdout);
+    let mut ids: Vec<&str> = stdout
+        .lines()
+        .filter_map(|line| line.split_once(' '))
+        .filter(|(_, path)| path.ends_with(".dvs"))
+        .map(|(id, _)| id)
+        .collect();
+    ids.sort();
+    ids.dedup();
+    if ids.is_empty() {
+        return Ok(Vec::new())
+    }
+
+    // read them all with a single git process
+    let mut cat_file = Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("could not run git in {}: {e}", repo_dir.display()))?;
+    let input = ids.join("\n") + "\n";
+    let mut stdin = cat_file.stdin.take().ok_or("could not write to git cat-file")?;
+    // write from another thread so a full stdout pipe can't block the write
+    let writer = std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = cat_file.wait_with_output()?;
+    writer.join().map_err(|_| "could not write to git cat-file")??;
+
+    // <id> <type> <size>\n<contents>\n for each object
+    let mut contents: Vec<String> = Vec::new();
+    let mut rest: &[u8] = &output.stdout;
+    while let Some(header_end) = rest.iter().position(|byte| *byte == b'\n') {
+        let header = String::from_utf8_lossy(&rest[..header_end]).to_string();
+        let fields: Vec<&str> = header.split(' ').collect();
+        rest = &rest[header_end + 1..];
+        if fields.len() != 3 {
+            // "<id> missing"
+            continue
+        }
+        let size: usize = fields[2].parse()?;
+        if fields[1] == "blob" {
+            contents.push(String::from_utf8_lossy(&rest[..size]).to_string());
+        }
+        rest = &rest[(size + 1).min(rest.len())..];
+    }
+    Ok(contents)
+}
+
+// what identifies a project wherever it's cloned: the url of its origin remote, or else the path of the repository
+pub fn repo_identity(repo_dir: &PathBuf) -> String {
+    Command::new("git")
+        .arg("-C")
+        .arg(repo_dir)
+        .args(["config", "--get", "remote.origin.url"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .filter(|url| !url.is_empty())
+        .unwrap_or(repo_dir.display().to_string())
+}
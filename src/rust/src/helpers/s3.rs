@@ -0,0 +1,342 @@
+use std::{env, fs::{self, File}, io::Read, path::PathBuf, time::SystemTime};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use url::Url;
+use crate::helpers::{config::{CredentialsSource, S3Config}, copy, outcome::Outcome, progress, storage::{ObjectInfo, StorageBackend}};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// uploads are streamed from disk, so their payload isn't hashed into the signature
+const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+// sha256 of an empty body
+const EMPTY_PAYLOAD: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+pub struct Credentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub session_token: Option<String>,
+}
+
+// objects stored under <bucket>/<prefix>/<hash[..2]>/<hash[2..]> on an S3-compatible endpoint,
+// addressed path-style so that MinIO and other stand-ins work without DNS setup
+pub struct S3Storage {
+    endpoint: Url,
+    host: String,
+    bucket: String,
+    prefix: String,
+    region: String,
+    credentials: Credentials,
+    agent: ureq::Agent,
+}
+
+impl S3Storage {
+    pub fn new(conf: &S3Config) -> Result<S3Storage> {
+        let endpoint = Url::parse(&conf.endpoint).map_err(|e|
+            format!("s3 endpoint: {} in dvs.yaml, {e}", conf.endpoint)
+        )?;
+
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => String::from(host),
+            (None, _) => return Err(format!("s3 endpoint: {} in dvs.yaml has no host", conf.endpoint).into()),
+        };
+
+        if conf.bucket.is_empty() {
+            return Err("s3 bucket not set in dvs.yaml".into());
+        }
+
+        Ok(S3Storage{
+            endpoint,
+            host,
+            bucket: conf.bucket.clone(),
+            prefix: conf.prefix.trim_matches('/').to_string(),
+            region: conf.region.clone(),
+            credentials: read_credentials(&conf.credentials, &conf.profile)?,
+            agent: ureq::AgentBuilder::new().build(),
+        })
+    }
+
+    // <prefix>/<hash[..2]>/<hash[2..]>; s3 storage always has the default layout, see migrate_layout
+    fn key(&self, hash: &String) -> String {
+        let key = format!("{}/{}", &hash[..2], &hash[2..]);
+        if self.prefix.is_empty() {key}
+        else {format!("{}/{key}", self.prefix)}
+    }
+
+    fn bucket_path(&self, key: &str) -> String {
+        let mut path = format!("/{}", uri_encode(&self.bucket, true));
+        if !key.is_empty() {
+            path = format!("{path}/{}", uri_encode(key, false));
+        }
+        path
+    }
+
+    // build a request signed with AWS signature version 4
+    fn request(&self, method: &str, key: &str, query: &Vec<(String, String)>, payload_hash: &str) -> ureq::Request {
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let short_date = now.format("%Y%m%d").to_string();
+        let scope = format!("{short_date}/{}/s3/aws4_request", self.region);
+
+        let path = self.bucket_path(key);
+        let mut sorted_query = query.clone();
+        sorted_query.sort();
+        let canonical_query = sorted_query
+            .iter()
+            .map(|(name, value)| format!("{}={}", uri_encode(name, true), uri_encode(value, true)))
+            .collect::<Vec<String>>()
+            .join("&");
+
+        // headers must be listed in sorted order
+        let mut headers: Vec<(&str, String)> = vec![
+            ("host", self.host.clone()),
+            ("x-amz-content-sha256", String::from(payload_hash)),
+            ("x-amz-date", amz_date.clone()),
+        ];
+        if let Some(token) = &self.credentials.session_token {
+            headers.push(("x-amz-security-token", token.clone()));
+        }
+        let canonical_headers: String = headers.iter().map(|(name, value)| format!("{name}:{value}\n")).collect();
+        let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<&str>>().join(";");
+
+        let canonical_request = format!("{method}\n{path}\n{canonical_query}\n{canonical_headers}\n{signed_headers}\n{payload_hash}");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut signing_key = hmac_sha256(format!("AWS4{}", self.credentials.secret_access_key).as_bytes(), &short_date);
+        for part in [self.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac_sha256(&signing_key, part);
+        }
+        let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={signature}",
+            self.credentials.access_key_id
+        );
+
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        url.set_query(if canonical_query.is_empty() {None} else {Some(&canonical_query)});
+
+        let mut request = self.agent.request(method, url.as_str());
+        for (name, value) in headers {
+            request = request.set(name, &value);
+        }
+        request.set("authorization", &authorization)
+    }
+
+    fn head(&self, hash: &String) -> Result<Option<ureq::Response>> {
+        match self.request("HEAD", &self.key(hash), &Vec::new(), EMPTY_PAYLOAD).call() {
+            Ok(response) => Ok(Some(response)),
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(request_error("HEAD", &self.location(hash), e)),
+        }
+    }
+}
+
+impl StorageBackend for S3Storage {
+    fn exists(&self, hash: &String) -> Result<bool> {
+        Ok(self.head(hash)?.is_some())
+    }
+
+    fn put(&self, local_path: &PathBuf, hash: &String) -> Result<Outcome> {
+        let file = File::open(local_path).map_err(|e|
+            format!("could not open source file: {}\n{e}", local_path.display())
+        )?;
+        let size = file.metadata()?.len();
+
+        self.request("PUT", &self.key(hash), &Vec::new(), UNSIGNED_PAYLOAD)
+            .set("content-length", &size.to_string())
+            .send(progress::Reader::new(file, Some(size)))
+            .map_err(|e| request_error("PUT", &self.location(hash), e))?;
+        Ok(Outcome::Copied)
+    }
+
+    fn get(&self, hash: &String, local_path: &PathBuf) -> Result<()> {
+        // only replace the local file if the download matches the hash it was stored under
+        copy::write_verified(&mut self.open(hash)?, hash, local_path)
+    }
+
+    fn open(&self, hash: &String) -> Result<Box<dyn Read + '_>> {
+        let response = self.request("GET", &self.key(hash), &Vec::new(), EMPTY_PAYLOAD)
+            .call()
+            .map_err(|e| request_error("GET", &self.location(hash), e))?;
+        Ok(Box::new(response.into_reader()))
+    }
+
+    fn delete(&self, hash: &String) -> Result<()> {
+        self.request("DELETE", &self.key(hash), &Vec::new(), EMPTY_PAYLOAD)
+            .call()
+            .map_err(|e| request_error("DELETE", &self.location(hash), e))?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let list_prefix = if self.prefix.is_empty() {String::new()} else {format!("{}/", self.prefix)};
+        let mut hashes: Vec<String> = Vec::new();
+        let mut continuation_token: Option<String> = None;
+
+        loop {
+            let mut query = vec![
+                (String::from("list-type"), String::from("2")),
+                (String::from("prefix"), list_prefix.clone()),
+            ];
+            if let Some(token) = &continuation_token {
+                query.push((String::from("continuation-token"), token.clone()));
+            }
+
+            let body = self.request("GET", "", &query, EMPTY_PAYLOAD)
+                .call()
+                .map_err(|e| request_error("GET", &format!("s3://{}/{list_prefix}", self.bucket), e))?
+                .into_string()?;
+
+            for key in xml_values(&body, "Key") {
+                // <prefix>/<hash[..2]>/<hash[2..]> -> <hash>
+                let segments: Vec<&str> = key[list_prefix.len()..].split('/').collect();
+                if segments.len() == 2 && segments[0].len() == 2 {
+                    hashes.push(segments.concat());
+                }
+            }
+
+            continuation_token = xml_values(&body, "NextContinuationToken").into_iter().next();
+            if continuation_token.is_none() {
+                break;
+            }
+        }
+        Ok(hashes)
+    }
+
+    fn stat(&self, hash: &String) -> Result<ObjectInfo> {
+        let response = self.head(hash)?
+            .ok_or_else(|| format!("{} not found", self.location(hash)))?;
+
+        let size = response
+            .header("content-length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .ok_or_else(|| format!("could not get size of {}", self.location(hash)))?;
+
+        let modification_time = response
+            .header("last-modified")
+            .and_then(|time| DateTime::parse_from_rfc2822(time).ok())
+            .map(SystemTime::from);
+
+        Ok(ObjectInfo{size, modification_time})
+    }
+
+    fn location(&self, hash: &String) -> String {
+        format!("s3://{}/{}", self.bucket, self.key(hash))
+    }
+}
+
+fn read_credentials(source: &CredentialsSource, profile: &Option<String>) -> Result<Credentials> {
+    match source {
+        CredentialsSource::Env => {
+            let access_key_id = env::var("AWS_ACCESS_KEY_ID").map_err(|e|
+                format!("could not read AWS_ACCESS_KEY_ID: {e}")
+            )?;
+            let secret_access_key = env::var("AWS_SECRET_ACCESS_KEY").map_err(|e|
+                format!("could not read AWS_SECRET_ACCESS_KEY: {e}")
+            )?;
+            Ok(Credentials{
+                access_key_id,
+                secret_access_key,
+                session_token: env::var("AWS_SESSION_TOKEN").ok(),
+            })
+        }
+        CredentialsSource::Profile => {
+            let profile = profile.clone().unwrap_or(String::from("default"));
+            let credentials_path = match env::var("AWS_SHARED_CREDENTIALS_FILE") {
+                Ok(path) => PathBuf::from(path),
+                Err(_) => PathBuf::from(env::var("HOME")?).join(".aws").join("credentials"),
+            };
+            let contents = fs::read_to_string(&credentials_path).map_err(|e|
+                format!("could not read credentials file {}: {e}", credentials_path.display())
+            )?;
+
+            // find the key = value pairs under [<profile>]
+            let mut in_profile = false;
+            let (mut access_key_id, mut secret_access_key, mut session_token) = (None, None, None);
+            for line in contents.lines().map(|line| line.trim()) {
+                if line.starts_with('[') && line.ends_with(']') {
+                    in_profile = line[1..line.len() - 1].trim() == profile;
+                    continue;
+                }
+                if !in_profile {
+                    continue;
+                }
+                if let Some((name, value)) = line.split_once('=') {
+                    let value = Some(value.trim().to_string());
+                    match name.trim() {
+                        "aws_access_key_id" => access_key_id = value,
+                        "aws_secret_access_key" => secret_access_key = value,
+                        "aws_session_token" => session_token = value,
+                        _ => {}
+                    }
+                }
+            }
+
+            Ok(Credentials{
+                access_key_id: access_key_id.ok_or_else(||
+                    format!("aws_access_key_id not found for profile {profile} in {}", credentials_path.display())
+                )?,
+                secret_access_key: secret_access_key.ok_or_else(||
+                    format!("aws_secret_access_key not found for profile {profile} in {}", credentials_path.display())
+                )?,
+                session_token,
+            })
+        }
+    }
+}
+
+fn request_error(method: &str, location: &String, e: ureq::Error) -> Error {
+    match e {
+        ureq::Error::Status(code, response) => {
+            let body = response.into_string().unwrap_or_default();
+            format!("{method} {location} failed with status {code}: {}", xml_values(&body, "Message").concat()).into()
+        }
+        e => format!("{method} {location} failed: {e}").into(),
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    // hmac accepts keys of any length
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac key");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+// percent-encode everything but unreserved characters (and '/' in paths), as sigv4 requires
+fn uri_encode(input: &str, encode_slash: bool) -> String {
+    input
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if !encode_slash => String::from("/"),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+// contents of every <tag>...</tag> in an s3 xml response
+fn xml_values(body: &str, tag: &str) -> Vec<String> {
+    let open = format!("<{tag}>");
+    let close = format!("</{tag}>");
+    body
+        .split(&open)
+        .skip(1)
+        .filter_map(|rest| rest.split_once(&close).map(|(value, _)| xml_unescape(value)))
+        .collect()
+}
+
+fn xml_unescape(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
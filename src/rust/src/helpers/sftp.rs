@@ -0,0 +1,242 @@
+use std::{env, fs::File, io::{self, Read}, net::TcpStream, path::PathBuf, time::{Duration, SystemTime}};
+use ssh2::{CheckResult, FileStat, KnownHostFileKind, RenameFlags, Session, Sftp};
+use crate::helpers::{config::SftpConfig, copy, layout, outcome::Outcome, progress, storage::{ObjectInfo, StorageBackend}};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+// a storage directory on a host reached over ssh, laid out as its .dvs_layout.yaml says
+pub struct SftpStorage {
+    host: String,
+    storage_dir: PathBuf,
+    layout: layout::Layout,
+    permissions: u32,
+    // remote group id to set on stored files
+    gid: Option<u32>,
+    // keep the session alive for as long as the sftp channel is in use
+    _session: Session,
+    sftp: Sftp,
+}
+
+impl SftpStorage {
+    pub fn new(conf: &SftpConfig, permissions: u32, group_name: &Option<String>) -> Result<SftpStorage> {
+        let user = match &conf.user {
+            Some(user) => user.clone(),
+            None => env::var("USER").map_err(|e| format!("sftp user not set in dvs.yaml and USER not found: {e}"))?,
+        };
+
+        let tcp = TcpStream::connect((conf.host.as_str(), conf.port)).map_err(|e|
+            format!("could not connect to {}:{}: {e}", conf.host, conf.port)
+        )?;
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(|e| format!("ssh handshake with {} failed: {e}", conf.host))?;
+
+        check_host_key(&session, &conf.host, conf.port)?;
+
+        match &conf.identity_file {
+            Some(identity_file) => session.userauth_pubkey_file(&user, None, identity_file, None),
+            None => session.userauth_agent(&user),
+        }.map_err(|e| format!("ssh authentication as {user}@{} failed: {e}", conf.host))?;
+
+        let gid = match group_name {
+            Some(group_name) if !group_name.is_empty() => Some(remote_gid(&session, group_name)?),
+            _ => None,
+        };
+
+        let sftp = session.sftp()?;
+        sftp.stat(&conf.storage_dir).map_err(|e|
+            format!("storage_dir: {} not found on {}: {e}", conf.storage_dir.display(), conf.host)
+        )?;
+        let layout = read_layout(&sftp, &conf.host, &conf.storage_dir)?;
+
+        Ok(SftpStorage{
+            host: conf.host.clone(),
+            storage_dir: conf.storage_dir.clone(),
+            layout,
+            permissions,
+            gid,
+            _session: session,
+            sftp,
+        })
+    }
+
+    // as layout::Layout::storage_path, checking for objects already moved on the remote host
+    fn storage_path(&self, hash: &String) -> PathBuf {
+        let current_path = layout::sharded_path(&self.storage_dir, hash, self.layout.sharding_depth);
+        if let Some(depth) = self.layout.migrating_to {
+            let migrated_path = layout::sharded_path(&self.storage_dir, hash, depth);
+            if self.sftp.stat(&migrated_path).is_ok() || self.sftp.stat(&current_path).is_err() {
+                return migrated_path
+            }
+        }
+        current_path
+    }
+
+    fn upload(&self, local_path: &PathBuf, temp_path: &PathBuf, storage_path: &PathBuf) -> Result<Outcome> {
+        let local_file = File::open(local_path).map_err(|e|
+            format!("could not open source file: {}\n{e}", local_path.display())
+        )?;
+        let size = local_file.metadata().ok().map(|metadata| metadata.len());
+        let mut remote_file = self.sftp.create(temp_path)?;
+        io::copy(&mut progress::Reader::new(local_file, size), &mut remote_file)?;
+        drop(remote_file);
+
+        // set file permissions and group on the remote side; sftp sets uid and gid together
+        let current = self.sftp.stat(temp_path)?;
+        self.sftp.setstat(temp_path, FileStat{
+            size: None,
+            uid: self.gid.and(current.uid),
+            gid: self.gid,
+            perm: Some(self.permissions),
+            atime: None,
+            mtime: None,
+        })?;
+
+        // never replace an object someone else put there first, see copy::Placement
+        if let Err(e) = self.sftp.rename(temp_path, storage_path, Some(RenameFlags::ATOMIC | RenameFlags::NATIVE)) {
+            if self.sftp.stat(storage_path).is_err() {
+                return Err(e.into())
+            }
+            let _ = self.sftp.unlink(temp_path);
+            return Ok(Outcome::Present)
+        }
+        Ok(Outcome::Copied)
+    }
+}
+
+impl StorageBackend for SftpStorage {
+    fn exists(&self, hash: &String) -> Result<bool> {
+        match self.sftp.stat(&self.storage_path(hash)) {
+            Ok(_) => Ok(true),
+            // LIBSSH2_FX_NO_SUCH_FILE
+            Err(e) if e.code() == ssh2::ErrorCode::SFTP(2) => Ok(false),
+            Err(e) => Err(format!("could not stat {}: {e}", self.location(hash)).into()),
+        }
+    }
+
+    fn put(&self, local_path: &PathBuf, hash: &String) -> Result<Outcome> {
+        let storage_path = self.storage_path(hash);
+        let parent = storage_path
+            .parent()
+            .ok_or_else(|| format!("could not access parent of copy destination: {}", self.location(hash)))?;
+        // each shard directory below storage_dir, as deep as the layout goes
+        let shards: Vec<&std::path::Path> = parent.ancestors().take_while(|dir| *dir != self.storage_dir.as_path()).collect();
+        for dir in shards.into_iter().rev() {
+            if self.sftp.stat(dir).is_err() {
+                self.sftp.mkdir(dir, 0o770).map_err(|e|
+                    format!("could not create {}:{}: {e}", self.host, dir.display())
+                )?;
+            }
+        }
+
+        // upload next to the final path, then move into place
+        let temp_path = copy::temp_path(&storage_path);
+        self.upload(local_path, &temp_path, &storage_path).map_err(|e| {
+            let _ = self.sftp.unlink(&temp_path);
+            format!("could not copy file {} to {}: {e}", local_path.display(), self.location(hash)).into()
+        })
+    }
+
+    fn get(&self, hash: &String, local_path: &PathBuf) -> Result<()> {
+        copy::write_verified(&mut self.open(hash)?, hash, local_path)
+    }
+
+    fn open(&self, hash: &String) -> Result<Box<dyn Read + '_>> {
+        let remote_file = self.sftp.open(&self.storage_path(hash)).map_err(|e|
+            format!("could not open {}: {e}", self.location(hash))
+        )?;
+        Ok(Box::new(remote_file))
+    }
+
+    fn delete(&self, hash: &String) -> Result<()> {
+        self.sftp.unlink(&self.storage_path(hash)).map_err(|e|
+            format!("could not remove {}: {e}", self.location(hash))
+        )?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        let mut hashes: Vec<String> = Vec::new();
+        for (shard_path, shard_stat) in self.sftp.readdir(&self.storage_dir)? {
+            let shard = shard_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+            if !shard_stat.is_dir() || shard.len() != 2 {
+                continue
+            }
+            for (object_path, object_stat) in self.sftp.readdir(&shard_path)? {
+                if !object_stat.is_file() {
+                    continue
+                }
+                let rest = object_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+                // skip uploads in progress
+                if rest.starts_with('.') {
+                    continue
+                }
+                hashes.push(format!("{shard}{rest}"));
+            }
+        }
+        Ok(hashes)
+    }
+
+    fn stat(&self, hash: &String) -> Result<ObjectInfo> {
+        let stat = self.sftp.stat(&self.storage_path(hash)).map_err(|e|
+            format!("could not get metadata for {}: {e}", self.location(hash))
+        )?;
+        Ok(ObjectInfo{
+            size: stat.size.unwrap_or_default(),
+            modification_time: stat.mtime.map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime)),
+        })
+    }
+
+    fn location(&self, hash: &String) -> String {
+        format!("{}:{}", self.host, self.storage_path(hash).display())
+    }
+}
+
+// the layout in storage_dir's .dvs_layout.yaml on the remote host, or the default if it has none
+fn read_layout(sftp: &Sftp, host: &String, storage_dir: &PathBuf) -> Result<layout::Layout> {
+    let layout_path = storage_dir.join(layout::LAYOUT_FILE);
+    let mut contents = String::new();
+    match sftp.open(&layout_path) {
+        Ok(mut file) => {
+            file.read_to_string(&mut contents).map_err(|e| format!("could not read {host}:{}: {e}", layout_path.display()))?;
+        }
+        // LIBSSH2_FX_NO_SUCH_FILE
+        Err(e) if e.code() == ssh2::ErrorCode::SFTP(2) => return Ok(layout::Layout::default()),
+        Err(e) => return Err(format!("could not open {host}:{}: {e}", layout_path.display()).into()),
+    }
+    Ok(layout::parse(&contents, &format!("{host}:{}", layout_path.display()))?)
+}
+
+// refuse hosts that aren't in ~/.ssh/known_hosts, as ssh would
+fn check_host_key(session: &Session, host: &String, port: u16) -> Result<()> {
+    let (key, _) = session.host_key().ok_or_else(|| format!("no host key from {host}"))?;
+    let mut known_hosts = session.known_hosts()?;
+    let known_hosts_path = PathBuf::from(env::var("HOME")?).join(".ssh").join("known_hosts");
+    known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH).map_err(|e|
+        format!("could not read {}: {e}", known_hosts_path.display())
+    )?;
+    match known_hosts.check_port(host, port, key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::NotFound => Err(format!("host key for {host} not found in {}; connect once with ssh to add it", known_hosts_path.display()).into()),
+        CheckResult::Mismatch => Err(format!("host key for {host} does not match {}", known_hosts_path.display()).into()),
+        CheckResult::Failure => Err(format!("could not check host key for {host}").into()),
+    }
+}
+
+// look up the id of a group on the remote host, since sftp only sets numeric ids
+fn remote_gid(session: &Session, group_name: &String) -> Result<u32> {
+    let mut channel = session.channel_session()?;
+    channel.exec(&format!("getent group '{}'", group_name.replace('\'', "")))?;
+    let mut output = String::new();
+    channel.read_to_string(&mut output)?;
+    channel.wait_close()?;
+
+    // <name>:<password>:<gid>:<members>
+    output
+        .lines()
+        .next()
+        .and_then(|line| line.split(':').nth(2))
+        .and_then(|gid| gid.trim().parse::<u32>().ok())
+        .ok_or_else(|| format!("change group: {group_name} in dvs.yaml, group not found on remote host").into())
+}
@@ -0,0 +1,642 @@
+use std::{ffi::CString, fs::{self, File}, io::Read, os::unix::{ffi::OsStrExt, fs::PermissionsExt}, path::{Path, PathBuf}, sync::Arc, time::SystemTime};
+use file_owner::Group;
+use walkdir::WalkDir;
+use crate::helpers::{chunk::{self, ChunkingConfig}, compress, config::{self, Backend, Compression, Config, Materialization}, copy, encrypt::{self, Key}, error::{BatchError, BatchErrorType, FileError, FileErrorType}, file, http::HttpStorage, layout::{self, Layout}, outcome::Outcome, progress, s3::S3Storage, sftp::SftpStorage};
+
+pub type Result<T> = core::result::Result<T, Error>;
+pub type Error = Box<dyn std::error::Error>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub modification_time: Option<SystemTime>,
+}
+
+// a place where versioned file copies are kept, addressed by their blake3 hash;
+// compressed and encrypted copies and chunk manifests are kept under the hash with a suffix
+// (see compress::zstd_key, encrypt::encrypted_key and chunk::manifest_key); shared by the threads that add and get files in parallel
+pub trait StorageBackend: Send + Sync {
+    // true if an object with the given hash is in storage
+    fn exists(&self, hash: &String) -> Result<bool>;
+
+    // copy the file at local_path into storage under the given hash; Present if someone else put it there first
+    fn put(&self, local_path: &PathBuf, hash: &String) -> Result<Outcome>;
+
+    // copy the object with the given hash out of storage to local_path
+    fn get(&self, hash: &String, local_path: &PathBuf) -> Result<()>;
+
+    // read the object with the given hash as stored, without checking it
+    fn open(&self, hash: &String) -> Result<Box<dyn Read + '_>>;
+
+    // remove the object with the given hash from storage
+    fn delete(&self, hash: &String) -> Result<()>;
+
+    // hashes of all objects in storage
+    fn list(&self) -> Result<Vec<String>>;
+
+    // size and modification time of the object with the given hash
+    fn stat(&self, hash: &String) -> Result<ObjectInfo>;
+
+    // where the object with the given hash lives, for messages
+    fn location(&self, hash: &String) -> String;
+
+    // path of the object with the given hash if it's on a locally mounted filesystem, so it can be linked to
+    fn local_path(&self, _hash: &String) -> Option<PathBuf> {
+        None
+    }
+}
+
+// storage directory on a locally mounted filesystem, laid out as its layout file says
+pub struct LocalStorage {
+    pub storage_dir: PathBuf,
+    pub permissions: u32,
+    pub group: Option<Group>,
+    pub layout: Layout,
+}
+
+impl LocalStorage {
+    pub fn storage_path(&self, key: &String) -> PathBuf {
+        self.layout.storage_path(&self.storage_dir, key)
+    }
+
+    // write an object whose contents come from a stream, e.g. an upload, if they match the hash;
+    // safe to run at the same time as other adds of the same object, see copy::Placement
+    pub fn put_verified(&self, reader: &mut dyn Read, key: &String) -> Result<Outcome> {
+        let storage_path = self.storage_path(key);
+        let hash = key_hash(key);
+        let placement = copy::Placement::Object{permissions: self.permissions, group: &self.group, check: &|path| object_matches(path, key)};
+        if compress::is_zstd_key(key) {
+            compress::write_verified(reader, &hash, &storage_path, &placement)
+        }
+        else if encrypt::is_encrypted_key(key) {
+            encrypt::write_object(reader, &hash, &storage_path, &placement)
+        }
+        else if chunk::is_manifest_key(key) {
+            chunk::write_manifest(reader, &hash, &storage_path, &placement)
+        }
+        else {
+            copy::write_placed(reader, key, &storage_path, &placement)
+        }
+    }
+}
+
+// true if the object stored at path under the given key matches the hash in it, as far as it can be checked
+fn object_matches(path: &PathBuf, key: &String) -> bool {
+    let hash = key_hash(key);
+    if compress::is_zstd_key(key) {
+        compress::object_matches(path, &hash)
+    }
+    else if encrypt::is_encrypted_key(key) {
+        encrypt::object_matches(path, &hash)
+    }
+    else if chunk::is_manifest_key(key) {
+        chunk::manifest_matches(path, &hash)
+    }
+    else {
+        copy::file_matches(path, key)
+    }
+}
+
+impl StorageBackend for LocalStorage {
+    fn exists(&self, hash: &String) -> Result<bool> {
+        Ok(self.storage_path(hash).exists())
+    }
+
+    fn put(&self, local_path: &PathBuf, hash: &String) -> Result<Outcome> {
+        let file = File::open(local_path).map_err(|e|
+            format!("could not open source file: {}\n{e}", local_path.display())
+        )?;
+        let size = file.metadata().ok().map(|metadata| metadata.len());
+        self.put_verified(&mut progress::Reader::new(file, size), hash)
+    }
+
+    fn get(&self, hash: &String, local_path: &PathBuf) -> Result<()> {
+        let storage_path = self.storage_path(hash);
+        copy::copy_impl(&storage_path, local_path, hash)
+    }
+
+    fn open(&self, hash: &String) -> Result<Box<dyn Read + '_>> {
+        let storage_path = self.storage_path(hash);
+        let file = File::open(&storage_path).map_err(|e|
+            format!("could not open {}: {e}", storage_path.display())
+        )?;
+        Ok(Box::new(file))
+    }
+
+    fn delete(&self, hash: &String) -> Result<()> {
+        let storage_path = self.storage_path(hash);
+        fs::remove_file(&storage_path).map_err(|e|
+            format!("could not remove {}: {e}", storage_path.display())
+        )?;
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<String>> {
+        Ok(walk_storage_dir(&self.storage_dir)?
+            .iter()
+            .filter_map(|storage_path| storage_path.strip_prefix(&self.storage_dir).ok())
+            .filter_map(|rel_path| parse_storage_path(rel_path))
+            .collect())
+    }
+
+    fn stat(&self, hash: &String) -> Result<ObjectInfo> {
+        let storage_path = self.storage_path(hash);
+        let metadata = storage_path.metadata().map_err(|e|
+            format!("could not get metadata for {}: {e}", storage_path.display())
+        )?;
+        Ok(ObjectInfo{
+            size: metadata.len(),
+            modification_time: metadata.modified().ok(),
+        })
+    }
+
+    fn location(&self, hash: &String) -> String {
+        self.storage_path(hash).display().to_string()
+    }
+
+    fn local_path(&self, hash: &String) -> Option<PathBuf> {
+        Some(self.storage_path(hash))
+    }
+}
+
+// every file in a local storage directory, skipping copies in progress and hidden directories, e.g. quarantined objects
+pub fn walk_storage_dir(storage_dir: &PathBuf) -> Result<Vec<PathBuf>> {
+    let mut storage_paths: Vec<PathBuf> = Vec::new();
+    let entries = WalkDir::new(storage_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.'));
+    for entry in entries {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            storage_paths.push(entry.into_path());
+        }
+    }
+    Ok(storage_paths)
+}
+
+// <hash[..2]>/<hash[2..]>, relative to the storage directory, with .zst, .enc or .chunks for
+// compressed and encrypted objects and manifests -> storage key; None for anything else.
+// any sharding depth is accepted, e.g. <hash[..2]>/<hash[2..4]>/<hash[4..]> or a flat <hash>
+pub fn parse_storage_path(rel_path: &Path) -> Option<String> {
+    let segments: Vec<String> = rel_path.iter().map(|segment| segment.to_string_lossy().to_string()).collect();
+    if segments.is_empty() || segments.len() as u32 > layout::MAX_SHARDING_DEPTH + 1 || segments[..segments.len() - 1].iter().any(|segment| segment.len() != 2) {
+        return None
+    }
+    // put the path segments back together: <hash[..2]>/<hash[2..]> -> <hash>
+    let key = segments.concat();
+    let hash = key_hash(&key);
+    if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)) {
+        Some(key)
+    }
+    else {
+        None
+    }
+}
+
+// storage key -> blake3 hash of the file it holds
+pub fn key_hash(key: &String) -> String {
+    key.trim_end_matches(compress::ZSTD_SUFFIX).trim_end_matches(encrypt::ENCRYPTED_SUFFIX).trim_end_matches(chunk::MANIFEST_SUFFIX).to_string()
+}
+
+// how new objects are written to storage
+#[derive(Clone, Debug)]
+pub enum ObjectFormat {
+    Raw,
+    // zstd at the given level
+    Zstd(i32),
+    // encrypted with the key, after compressing at the level if there is one
+    Encrypted(Arc<Key>, Option<i32>),
+}
+
+impl ObjectFormat {
+    // storage key of the object for a hash written in this format
+    pub fn key(&self, hash: &String) -> String {
+        match self {
+            ObjectFormat::Raw => hash.clone(),
+            ObjectFormat::Zstd(_) => compress::zstd_key(hash),
+            ObjectFormat::Encrypted(..) => encrypt::encrypted_key(hash),
+        }
+    }
+}
+
+// the key is loaded once, so a missing key stops the batch before any file is added
+pub fn object_format(conf: &Config) -> std::result::Result<ObjectFormat, BatchError> {
+    let level = match conf.compression {
+        Compression::None => None,
+        Compression::Zstd => Some(conf.compression_level.unwrap_or(zstd::DEFAULT_COMPRESSION_LEVEL)),
+    };
+    match (&conf.encryption, level) {
+        (Some(encryption), level) => {
+            let key = encrypt::load_key(&encryption.key_id).map_err(|e|
+                BatchError{
+                    error: BatchErrorType::EncryptionKeyNotFound,
+                    error_message: format!("check encryption in dvs.yaml, {e}")
+                }
+            )?;
+            Ok(ObjectFormat::Encrypted(Arc::new(key), level))
+        }
+        (None, Some(level)) => Ok(ObjectFormat::Zstd(level)),
+        (None, None) => Ok(ObjectFormat::Raw),
+    }
+}
+
+// one of the places objects are kept: the primary storage in dvs.yaml, then each replica in order
+pub struct Location {
+    // storage directory, bucket, or url, for messages
+    pub name: String,
+    pub required: bool,
+    // an unreachable location is kept so it can be reported per file
+    pub backend: std::result::Result<Box<dyn StorageBackend>, BatchError>,
+}
+
+// storage_dir from dvs.yaml, regardless of the configured backend
+pub fn local_from_config(conf: &Config) -> std::result::Result<LocalStorage, BatchError> {
+    local_from_dir(&conf.storage_dir, conf)
+}
+
+// a local storage directory with the permissions and group from dvs.yaml
+fn local_from_dir(storage_dir: &PathBuf, conf: &Config) -> std::result::Result<LocalStorage, BatchError> {
+    // get group, check if specified
+    let group = config::get_group(&conf.group.clone().unwrap_or_default())?;
+
+    // check storage directory exists
+    let storage_dir = config::get_storage_dir(storage_dir)?;
+
+    // get file permissions
+    let permissions = config::get_mode_u32(&conf.permissions.unwrap_or(664))?;
+
+    // get layout
+    let layout = layout::read(&storage_dir)?;
+
+    Ok(LocalStorage{storage_dir, permissions, group, layout})
+}
+
+// build the storage backend configured in dvs.yaml
+pub fn from_config(conf: &Config) -> std::result::Result<Box<dyn StorageBackend>, BatchError> {
+    build(&conf.storage_dir, &conf.backend, conf)
+}
+
+// the primary storage followed by the replicas in dvs.yaml
+pub fn locations_from_config(conf: &Config) -> Vec<Location> {
+    let primary = Location{
+        name: location_name(&conf.storage_dir, &conf.backend),
+        required: true,
+        backend: from_config(conf),
+    };
+    let replicas = conf.replicas.iter().map(|replica| Location{
+        name: location_name(&replica.storage_dir, &replica.backend),
+        required: replica.required,
+        backend: build(&replica.storage_dir, &replica.backend, conf),
+    });
+    std::iter::once(primary).chain(replicas).collect()
+}
+
+fn build(storage_dir: &PathBuf, backend: &Backend, conf: &Config) -> std::result::Result<Box<dyn StorageBackend>, BatchError> {
+    match backend {
+        Backend::Local => Ok(Box::new(local_from_dir(storage_dir, conf)?)),
+        Backend::S3(s3_config) => {
+            let s3 = S3Storage::new(s3_config).map_err(|e| backend_error(e))?;
+            Ok(Box::new(s3))
+        }
+        Backend::Sftp(sftp_config) => {
+            // permissions and group are set on the remote side
+            let permissions = config::get_mode_u32(&conf.permissions.unwrap_or(664))?;
+            let sftp = SftpStorage::new(sftp_config, permissions, &conf.group).map_err(|e| backend_error(e))?;
+            Ok(Box::new(sftp))
+        }
+        Backend::Http(http_config) => {
+            let http = HttpStorage::new(http_config, false).map_err(|e| backend_error(e))?;
+            Ok(Box::new(http))
+        }
+        Backend::Server(http_config) => {
+            let server = HttpStorage::new(http_config, true).map_err(|e| backend_error(e))?;
+            Ok(Box::new(server))
+        }
+    }
+}
+
+fn location_name(storage_dir: &PathBuf, backend: &Backend) -> String {
+    match backend {
+        Backend::Local => storage_dir.display().to_string(),
+        Backend::S3(s3_config) if s3_config.prefix.is_empty() => format!("s3://{}", s3_config.bucket),
+        Backend::S3(s3_config) => format!("s3://{}/{}", s3_config.bucket, s3_config.prefix.trim_matches('/')),
+        Backend::Sftp(sftp_config) => format!("{}:{}", sftp_config.host, sftp_config.storage_dir.display()),
+        Backend::Http(http_config) | Backend::Server(http_config) => http_config.url.clone(),
+    }
+}
+
+fn backend_error(e: Error) -> BatchError {
+    BatchError{
+        error: BatchErrorType::StorageBackendInvalid,
+        error_message: format!("check backend in dvs.yaml, {e}")
+    }
+}
+
+// backends return boxed errors; keep a FileError if that's what the backend gave back
+fn storage_error(local_path: &PathBuf, error: FileErrorType, e: Error) -> FileError {
+    let error = if e.is::<copy::HashMismatch>() {FileErrorType::HashMismatch} else {error};
+    match e.downcast::<FileError>() {
+        Ok(file_error) => *file_error,
+        Err(e) => FileError{
+            relative_path: file::try_to_get_rel_path(local_path),
+            absolute_path: file::try_to_get_abs_path(local_path),
+            error,
+            error_message: Some(e.to_string()),
+            input: local_path.clone()
+        }
+    }
+}
+
+pub fn put(backend: &dyn StorageBackend, local_path: &PathBuf, hash: &String, format: &ObjectFormat) -> std::result::Result<Outcome, FileError> {
+    let encoded = match format {
+        ObjectFormat::Raw => return backend.put(local_path, hash).map_err(|e| storage_error(local_path, FileErrorType::FileNotCopied, e)),
+        ObjectFormat::Zstd(level) => staging_path(backend, &compress::zstd_key(hash), local_path)
+            .and_then(|temp_path| compress::compress_to_temp(local_path, *level, &temp_path).map(|_| (temp_path, compress::zstd_key(hash)))),
+        ObjectFormat::Encrypted(key, level) => staging_path(backend, &encrypt::encrypted_key(hash), local_path)
+            .and_then(|temp_path| encrypt::encrypt_to_temp(local_path, hash, key, *level, &temp_path).map(|_| (temp_path, encrypt::encrypted_key(hash)))),
+    };
+    let (temp_path, key) = encoded.map_err(|e| storage_error(local_path, FileErrorType::FileNotCopied, e))?;
+    let result = backend.put(&temp_path, &key);
+    let _ = fs::remove_file(&temp_path);
+    // report errors against the file being added rather than the temporary file
+    result.map_err(|e| match e.downcast::<FileError>() {
+        Ok(file_error) => storage_error(local_path, file_error.error.clone(), file_error.to_string().into()),
+        Err(e) => storage_error(local_path, FileErrorType::FileNotCopied, e),
+    })
+}
+
+// temporary file an encoded object is written to before it's put in storage: beside where it goes in local storage,
+// so it's on the same filesystem as the object, or beside the file being added for other storage
+pub fn staging_path(backend: &dyn StorageBackend, key: &String, local_path: &PathBuf) -> Result<PathBuf> {
+    let beside = backend.local_path(key).unwrap_or_else(|| local_path.clone());
+    if let Some(parent) = beside.parent() {
+        fs::create_dir_all(parent).map_err(|e| format!("could not create {}: {e}", parent.display()))?;
+    }
+    Ok(copy::temp_path(&beside))
+}
+
+// store the file as content-defined chunks and a manifest
+pub fn put_chunked(backend: &dyn StorageBackend, local_path: &PathBuf, hash: &String, format: &ObjectFormat, chunking: &ChunkingConfig) -> std::result::Result<Outcome, FileError> {
+    chunk::put(backend, local_path, hash, format, chunking).map_err(|e|
+        storage_error(local_path, FileErrorType::FileNotCopied, e.to_string().into())
+    )
+}
+
+pub fn get(backend: &dyn StorageBackend, hash: &String, local_path: &PathBuf) -> std::result::Result<(), FileError> {
+    let stored_as = |key: String| backend.exists(&key).map_err(|e| storage_error(local_path, FileErrorType::StorageNotReachable, e));
+
+    let result =
+        // reassemble chunked files from their manifest
+        if stored_as(chunk::manifest_key(hash))? {
+            chunk::get(backend, hash, local_path)
+        }
+        // decrypt and/or decompress as the object is read, checking the plaintext against the hash
+        else if stored_as(compress::zstd_key(hash))? || stored_as(encrypt::encrypted_key(hash))? {
+            open(backend, hash).and_then(|mut reader| copy::write_verified(&mut reader, hash, local_path))
+        }
+        else {
+            backend.get(hash, local_path)
+        };
+    result.map_err(|e| storage_error(local_path, FileErrorType::FileNotCopied, e))
+}
+
+// read the uncompressed and decrypted contents of a whole object
+pub fn open<'a>(backend: &'a dyn StorageBackend, hash: &String) -> Result<Box<dyn Read + 'a>> {
+    let zstd_key = compress::zstd_key(hash);
+    let encrypted_key = encrypt::encrypted_key(hash);
+    if backend.exists(&zstd_key)? {
+        compress::decoder(backend.open(&zstd_key)?)
+    }
+    else if backend.exists(&encrypted_key)? {
+        encrypt::decoder(backend.open(&encrypted_key)?, hash)
+    }
+    else {
+        backend.open(hash)
+    }
+}
+
+// read the contents of a whole object in any format, reassembling a chunked one
+pub fn open_object<'a>(backend: &'a dyn StorageBackend, hash: &String) -> Result<Box<dyn Read + 'a>> {
+    if backend.exists(&chunk::manifest_key(hash))? {
+        chunk::open(backend, hash)
+    }
+    else {
+        open(backend, hash)
+    }
+}
+
+// true if the object is in storage in any format
+pub fn exists(backend: &dyn StorageBackend, hash: &String, local_path: &PathBuf) -> std::result::Result<bool, FileError> {
+    for key in [hash.clone(), compress::zstd_key(hash), encrypt::encrypted_key(hash), chunk::manifest_key(hash)] {
+        if backend.exists(&key).map_err(|e| storage_error(local_path, FileErrorType::StorageNotReachable, e))? {
+            return Ok(true)
+        }
+    }
+    Ok(false)
+}
+
+// false if the object is in local storage but doesn't match its hash, so adding its file again replaces it;
+// objects in other storage are only checked when they're retrieved
+pub fn intact(backend: &dyn StorageBackend, hash: &String) -> bool {
+    for key in [hash.clone(), compress::zstd_key(hash), encrypt::encrypted_key(hash), chunk::manifest_key(hash)] {
+        if let Some(path) = backend.local_path(&key).filter(|path| path.exists()) {
+            return object_matches(&path, &key)
+        }
+    }
+    true
+}
+
+// mark the object with the given hash, and the chunks of a chunked one, as just used so gc's grace period starts again:
+// adding a file whose object is already stored must keep it from being deleted before its .dvs file is pushed.
+// only local storage has the modification times gc reads, and objects that can't be written to keep theirs
+pub fn touch(backend: &dyn StorageBackend, hash: &String) {
+    touch_formats(backend, hash);
+    if backend.local_path(&chunk::manifest_key(hash)).map_or(false, |path| path.exists()) {
+        if let Ok(manifest) = chunk::read_manifest(backend, hash) {
+            for chunk in manifest.chunks {
+                touch_formats(backend, &chunk.blake3_checksum);
+            }
+        }
+    }
+}
+
+fn touch_formats(backend: &dyn StorageBackend, hash: &String) {
+    for key in [hash.clone(), compress::zstd_key(hash), encrypt::encrypted_key(hash), chunk::manifest_key(hash)] {
+        touch_key(backend, &key);
+    }
+}
+
+// touch, for the object stored under a single key; keys with nothing stored under them are skipped
+pub fn touch_key(backend: &dyn StorageBackend, key: &String) {
+    if let Some(path) = backend.local_path(key) {
+        let _ = touch_path(&path);
+    }
+}
+
+// set the modification time to now; unlike setting a given time, which needs ownership,
+// this only needs write permission, so it works on objects other group members stored
+fn touch_path(path: &Path) -> std::io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec{tv_sec: 0, tv_nsec: libc::UTIME_OMIT},
+        libc::timespec{tv_sec: 0, tv_nsec: libc::UTIME_NOW},
+    ];
+    match unsafe { libc::utimensat(libc::AT_FDCWD, c_path.as_ptr(), times.as_ptr(), 0) } {
+        0 => Ok(()),
+        _ => Err(std::io::Error::last_os_error()),
+    }
+}
+
+// remove the object with the given hash in whichever format it was stored;
+// chunks are left for other versions that share them
+pub fn delete(backend: &dyn StorageBackend, hash: &String) -> Result<()> {
+    let raw = backend.delete(hash);
+    let compressed = backend.delete(&compress::zstd_key(hash));
+    let encrypted = backend.delete(&encrypt::encrypted_key(hash));
+    let manifest = backend.delete(&chunk::manifest_key(hash));
+    raw.or(compressed).or(encrypted).or(manifest)
+}
+
+// where the object for a hash is, in whichever format it was stored, for messages
+pub fn object_location(backend: &dyn StorageBackend, hash: &String) -> String {
+    [chunk::manifest_key(hash), compress::zstd_key(hash), encrypt::encrypted_key(hash)]
+        .into_iter()
+        .find(|key| backend.exists(key).unwrap_or(false))
+        .map_or(backend.location(hash), |key| backend.location(&key))
+}
+
+// a stored object whose contents don't match the hash it's stored under
+pub fn corrupt_object_error(local_path: &PathBuf, object_location: &String, hash: &String, actual_hash: &String) -> FileError {
+    FileError{
+        relative_path: file::try_to_get_rel_path(local_path),
+        absolute_path: file::try_to_get_abs_path(local_path),
+        error: FileErrorType::StorageObjectCorrupt,
+        error_message: Some(format!("{object_location} is corrupt: retrieved contents hash to {actual_hash}, not {hash}; check storage with dvs_fsck()")),
+        input: local_path.clone()
+    }
+}
+
+// copy the object out of the first location that has a good copy of it, returning where it came from
+pub fn get_from_locations(locations: &Vec<Location>, hash: &String, local_path: &PathBuf) -> std::result::Result<String, FileError> {
+    let mut errors: Vec<FileError> = Vec::new();
+    for location in locations {
+        let result = match &location.backend {
+            Ok(backend) => get(backend.as_ref(), hash, local_path)
+                .map(|_| object_location(backend.as_ref(), hash))
+                .map_err(|e| match e.error {
+                    // name the bad object rather than the file it was copied to
+                    FileErrorType::HashMismatch => FileError{
+                        error: FileErrorType::StorageObjectCorrupt,
+                        error_message: Some(format!("{} is corrupt: {}; check storage with dvs_fsck()", object_location(backend.as_ref(), hash), e.error_message.clone().unwrap_or_default())),
+                        ..e
+                    },
+                    _ => e,
+                }),
+            Err(e) => Err(FileError{
+                relative_path: file::try_to_get_rel_path(local_path),
+                absolute_path: file::try_to_get_abs_path(local_path),
+                error: FileErrorType::StorageNotReachable,
+                error_message: Some(e.error_message.clone()),
+                input: local_path.clone()
+            }),
+        };
+        match result {
+            Ok(object_location) => return Ok(object_location),
+            Err(e) => errors.push(e),
+        }
+    }
+
+    // with a single location, pass its error through as is
+    if errors.len() == 1 {
+        return Err(errors.remove(0))
+    }
+    Err(FileError{
+        relative_path: file::try_to_get_rel_path(local_path),
+        absolute_path: file::try_to_get_abs_path(local_path),
+        error: FileErrorType::FileNotCopied,
+        error_message: Some(
+            locations
+                .iter()
+                .zip(errors)
+                .map(|(location, e)| format!("{}: {}", location.name, e.error_message.unwrap_or_default()))
+                .collect::<Vec<String>>()
+                .join("; ")
+        ),
+        input: local_path.clone()
+    })
+}
+
+// open the object like open_object from the first location that has it, without checking it; returns where it is
+pub fn open_from_locations<'a>(locations: &'a Vec<Location>, hash: &String, local_path: &PathBuf) -> std::result::Result<(Box<dyn Read + 'a>, String), FileError> {
+    let mut errors: Vec<String> = Vec::new();
+    for location in locations {
+        match &location.backend {
+            Ok(backend) => match open_object(backend.as_ref(), hash) {
+                Ok(reader) => return Ok((reader, object_location(backend.as_ref(), hash))),
+                Err(e) => errors.push(format!("{}: {e}", location.name)),
+            },
+            Err(e) => errors.push(format!("{}: {}", location.name, e.error_message)),
+        }
+    }
+    Err(storage_error(local_path, FileErrorType::FileNotCopied, errors.join("; ").into()))
+}
+
+// get the file like get_from_locations, but link or reflink it to its object instead of copying it when
+// the object is stored whole and uncompressed on a locally mounted filesystem and the link can be made;
+// returns where the object is
+pub fn materialize_from_locations(locations: &Vec<Location>, hash: &String, local_path: &PathBuf, materialization: Materialization) -> std::result::Result<(Outcome, String), FileError> {
+    // a link in the workspace must never be written through, or the object it points to would change
+    file::remove_link(local_path)?;
+
+    if materialization != Materialization::Copy {
+        let object_path = locations
+            .iter()
+            .filter_map(|location| location.backend.as_ref().ok())
+            .filter_map(|backend| backend.local_path(hash))
+            .find(|object_path| object_path.is_file());
+        // e.g. a hardlink across filesystems falls back to copying
+        if let Some(object_path) = object_path {
+            if let Ok(outcome) = link(&object_path, local_path, materialization) {
+                return Ok((outcome, object_path.display().to_string()))
+            }
+        }
+    }
+
+    Ok((Outcome::Copied, get_from_locations(locations, hash, local_path)?))
+}
+
+// made beside the file and renamed over it, so the file is replaced in one step
+fn link(object_path: &PathBuf, local_path: &PathBuf, materialization: Materialization) -> Result<Outcome> {
+    let file_name = local_path.file_name().ok_or_else(|| format!("no file name: {}", local_path.display()))?;
+    // unique, so gets of the same file at once don't make their links in the same place
+    let temp_path = copy::temp_path(&local_path.with_file_name(format!("{}.dvs-link", file_name.to_string_lossy())));
+
+    // a linked workspace file is the object, so writing to it would change the object for everyone; only objects
+    // nobody can write to, e.g. stored with permissions: 444, are linked, and their modes are never changed here
+    if matches!(materialization, Materialization::Hardlink | Materialization::Symlink) && object_path.metadata()?.permissions().mode() & 0o222 != 0 {
+        return Err(format!("{} can be written to, so it's copied instead of linked", object_path.display()).into())
+    }
+
+    let outcome = match materialization {
+        Materialization::Copy => return Err("not a link".into()),
+        Materialization::Reflink => {
+            reflink_copy::reflink(object_path, &temp_path)?;
+            Outcome::Copied
+        }
+        Materialization::Hardlink => {
+            fs::hard_link(object_path, &temp_path)?;
+            Outcome::Linked
+        }
+        Materialization::Symlink => {
+            std::os::unix::fs::symlink(object_path, &temp_path)?;
+            Outcome::Linked
+        }
+    };
+
+    fs::rename(&temp_path, local_path).map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        e
+    })?;
+    Ok(outcome)
+}
@@ -1,10 +1,12 @@
 mod helpers;
 mod library;
-use helpers::{outcome::{Outcome, Status}, parse};
-use library::{init, add, get, status, info};
+use helpers::{annotation::{AnnotationEdit, AnnotationFilter}, config, directory::MemberStatus, error::FileError, outcome::{Outcome, Status}, parse, progress::{Progress, ProgressUpdate}};
+use library::{init, add, get, status, info, serve, fsck, gc, usage, migrate_layout, lookup, migrate_metadata, annotate, verify};
 use extendr_api::{prelude::*,  Robj};
 use std::path::PathBuf;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}};
+use std::time::Duration;
 
 #[derive(Clone, PartialEq, Debug, IntoDataFrameRow)]
 struct RFile {
@@ -28,6 +30,31 @@ struct RFileSuccess {
     absolute_path: String,
 }
 
+// added files df: copy outcome per storage location when replicas are configured
+#[derive(Clone, PartialEq, Debug, IntoDataFrameRow)]
+struct RAddedFile {
+    relative_path: Option<String>,
+    outcome: String,
+    size: Option<u64>,
+    blake3_checksum: Option<String>,
+    absolute_path: Option<String>,
+    replicas: Option<String>,
+    input: Option<String>,
+    error: Option<String>,
+    error_message: Option<String>,
+}
+
+// added files success df
+#[derive(Clone, PartialEq, IntoDataFrameRow)]
+struct RAddedFileSuccess {
+    relative_path: String,
+    outcome: String,
+    size: u64,
+    blake3_checksum: String,
+    absolute_path: String,
+    replicas: Option<String>,
+}
+
 // error df
 #[derive(Debug, IntoDataFrameRow)]
 struct RFileError {
@@ -68,45 +95,61 @@ fn dvs_init_impl(storage_dir: &str, mode: Nullable<i32>, group: Nullable<&str>)
 
 
 #[extendr]
-fn dvs_add_impl(files_string: Vec<String>, message: Nullable<&str>, strict: bool, split_output: bool) -> Result<Robj> {
+fn dvs_add_impl(files_string: Vec<String>, message: Nullable<&str>, strict: bool, tags: Vec<String>, annotation_names: Vec<String>, annotation_values: Vec<String>, split_output: bool, progress: Nullable<Function>, workers: Nullable<f64>) -> Result<Robj> {
     let files_in: Vec<PathBuf> = files_string.into_iter().map(PathBuf::from).collect();
     let message_in = <Option<&str>>::from(message);
+    let annotations = AnnotationEdit{
+        add_tags: tags,
+        set: annotations_from_r(annotation_names, annotation_values),
+        ..Default::default()
+    };
 
-    let added_files = add::add(&files_in, message_in, strict).map_err(|e| {
+    let added_files = with_progress(progress, |progress| add::add(&files_in, message_in, strict, &annotations, workers_from_r(workers), progress))?.map_err(|e| {
         Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
     })?;
 
     let results = added_files
         .iter()
         .map(|fi| match fi {
-            Ok(fi) => RFile{
+            Ok(fi) => RAddedFile{
                 relative_path: Some(fi.relative_path.display().to_string()),
                 outcome: fi.outcome.outcome_to_string(),
                 size: Some(fi.size),
                 blake3_checksum: Some(fi.blake3_checksum.clone()),
                 absolute_path: Some(fi.absolute_path.display().to_string()),
+                replicas: replicas_to_string(&fi.replicas),
                 input: None,
                 error: None,
                 error_message: None,
             },
-            Err(e) => RFile{
+            Err(e) => RAddedFile{
                 relative_path: e.relative_path.clone().map(|p| p.to_string_lossy().to_string()),
                 outcome: Outcome::Error.outcome_to_string(),
                 size: None,
                 blake3_checksum:  None,
                 absolute_path: e.absolute_path.clone().map(|p| p.to_string_lossy().to_string()),
+                replicas: None,
                 input: Some(e.input.display().to_string()),
                 error: Some(e.error.file_error_to_string()),
                 error_message: e.error_message.clone(),
             }
         })
-        .collect::<Vec<RFile>>();
+        .collect::<Vec<RAddedFile>>();
+
+    // versions added that the storage directory's index is missing
+    let warnings = added_files
+        .iter()
+        .filter_map(|fi| fi.as_ref().ok())
+        .filter_map(|fi| fi.index_error.as_ref().map(|e|
+            format!("{} was added but not recorded in the storage directory's index, so dvs_lookup() won't find this version: {e}", fi.relative_path.display())
+        ))
+        .collect::<Vec<String>>();
 
     if !split_output {
-        Ok(results
+        with_warnings(results
             .into_dataframe()
             .map_err(|e| Error::Other(format!("Error converting added files to data frame: {e}")))?
-            .as_robj().clone())
+            .as_robj().clone(), warnings)
     }
     else {
         let failures = results
@@ -130,17 +173,18 @@ fn dvs_add_impl(files_string: Vec<String>, message: Nullable<&str>, strict: bool
             .filter_map(|res| {
                 if res.error.is_none() {
                     Some(
-                        RFileSuccess{
+                        RAddedFileSuccess{
                             relative_path: res.relative_path.unwrap(),
                             outcome: res.outcome,
                             size: res.size.unwrap(),
                             blake3_checksum: res.blake3_checksum.unwrap(),
                             absolute_path: res.absolute_path.unwrap(),
+                            replicas: res.replicas,
                         }
                     )
                 }
                 else {None}
-            }).collect::<Vec<RFileSuccess>>();
+            }).collect::<Vec<RAddedFileSuccess>>();
 
             let mut result = HashMap::new();
             if successes.len() > 0 {
@@ -156,18 +200,139 @@ fn dvs_add_impl(files_string: Vec<String>, message: Nullable<&str>, strict: bool
                 );
             }
 
-            Ok(List::from_hashmap(result).map_err(|e|Error::Other(format!("Error converting added files to data frame: {e}"))).into_robj())
+            with_warnings(List::from_hashmap(result).map_err(|e|Error::Other(format!("Error converting added files to data frame: {e}"))).into_robj(), warnings)
     }
 }
 
+// run a batch, passing its progress to an R callback if there is one. R can only be called from this thread,
+// so the batch runs on another one while this one checks for interrupts and hands the callback the latest update
+// every so often; the batch is cancelled once the user interrupts it or the callback returns FALSE, and the
+// callback's error is returned if it fails
+fn with_progress<T: Send>(callback: Nullable<Function>, run: impl FnOnce(Option<&Arc<Progress>>) -> T + Send) -> Result<T> {
+    let latest: Arc<Mutex<Option<ProgressUpdate>>> = Arc::new(Mutex::new(None));
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let progress = Arc::new(Progress::new({
+        let latest = latest.clone();
+        let cancelled = cancelled.clone();
+        move |update| {
+            if let Ok(mut latest) = latest.lock() {
+                *latest = Some(update.clone());
+            }
+            !cancelled.load(Ordering::Relaxed)
+        }
+    }));
+
+    let mut callback_error: Option<Error> = None;
+    let result = std::thread::scope(|scope| {
+        let progress = &progress;
+        let batch = scope.spawn(move || run(Some(progress)));
+        loop {
+            let finished = batch.is_finished();
+            if user_interrupted() {
+                cancelled.store(true, Ordering::Relaxed);
+            }
+            let update = latest.lock().ok().and_then(|mut latest| latest.take()).filter(|_| callback_error.is_none());
+            if let (Nullable::NotNull(callback), Some(update)) = (&callback, update) {
+                let keep_going = callback.call(pairlist!(
+                    files_done = update.files_done as i32,
+                    files_total = update.files_total as i32,
+                    file_index = update.file_index as i32,
+                    path = update.path.display().to_string(),
+                    stage = update.stage.stage_to_string(),
+                    bytes = update.bytes as f64,
+                    size = update.size.map_or(Rfloat::na(), |size| Rfloat::from(size as f64)),
+                    bytes_hashed = update.bytes_hashed as f64,
+                    bytes_copied = update.bytes_copied as f64
+                ));
+                match keep_going {
+                    Ok(keep_going) if keep_going.as_bool() == Some(false) => cancelled.store(true, Ordering::Relaxed),
+                    Ok(_) => {},
+                    Err(e) => {
+                        cancelled.store(true, Ordering::Relaxed);
+                        callback_error = Some(e);
+                    }
+                }
+            }
+            if finished {
+                break
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        batch.join().unwrap_or_else(|e| std::panic::resume_unwind(e))
+    });
+
+    match callback_error {
+        Some(e) => Err(Error::Other(format!("progress callback failed, so the operation was cancelled: {e}"))),
+        None => Ok(result),
+    }
+}
+
+extern "C" {
+    fn R_CheckUserInterrupt();
+    fn R_ToplevelExec(fun: Option<unsafe extern "C" fn(*mut std::ffi::c_void)>, data: *mut std::ffi::c_void) -> std::os::raw::c_int;
+}
+
+unsafe extern "C" fn check_user_interrupt(_data: *mut std::ffi::c_void) {
+    R_CheckUserInterrupt();
+}
+
+// true if the user has interrupted R since it last checked; R_CheckUserInterrupt jumps out of the
+// function it's called from on an interrupt, so it's called at the top level, which catches the jump
+fn user_interrupted() -> bool {
+    unsafe { R_ToplevelExec(Some(check_user_interrupt), std::ptr::null_mut()) == 0 }
+}
+
+// "<location>: <outcome>" for each storage location, or NA when there's only the primary one
+fn replicas_to_string(replicas: &Vec<add::ReplicaOutcome>) -> Option<String> {
+    if replicas.len() < 2 {
+        return None
+    }
+    Some(replicas
+        .iter()
+        .map(|replica| match &replica.error_message {
+            Some(message) => format!("{}: {} ({message})", replica.location, replica.outcome.outcome_to_string()),
+            None => format!("{}: {}", replica.location, replica.outcome.outcome_to_string()),
+        })
+        .collect::<Vec<String>>()
+        .join("; "))
+}
+
 #[extendr]
-fn dvs_get_impl(files_string: Vec<String>, split_output: bool) -> Result<Robj> {
+fn dvs_get_impl(files_string: Vec<String>, split_output: bool, materialization: Nullable<&str>, mtime: Nullable<&str>, progress: Nullable<Function>, workers: Nullable<f64>) -> Result<Robj> {
     let files_pathbuf: Vec<PathBuf> = files_string.into_iter().map(PathBuf::from).collect();
 
-    let got_files = get::get(&files_pathbuf).map_err(|e|
+    let materialization_in = match <Option<&str>>::from(materialization) {
+        Some(name) => Some(config::parse_materialization(name).map_err(|e|
+            Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+        )?),
+        None => None,
+    };
+
+    let mtime_in = match <Option<&str>>::from(mtime) {
+        Some(name) => Some(config::parse_mtime(name).map_err(|e|
+            Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+        )?),
+        None => None,
+    };
+
+    let got_files = with_progress(progress, |progress| get::get(&files_pathbuf, materialization_in, mtime_in, workers_from_r(workers), progress))?.map_err(|e|
         Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
     )?;
 
+    // files in retrieved directories that aren't in the version retrieved are left in place
+    let warnings = got_files
+        .iter()
+        .flatten()
+        .filter(|fi| !fi.untracked.is_empty())
+        .map(|fi| {
+            let mut paths = fi.untracked.iter().take(5).map(|p| p.display().to_string()).collect::<Vec<String>>();
+            if fi.untracked.len() > paths.len() {
+                paths.push(String::from("..."));
+            }
+            format!("{} file(s) in {} aren't in the version retrieved and were left in place: {}", fi.untracked.len(), fi.relative_path.display(), paths.join(", "))
+        })
+        .collect::<Vec<String>>();
+
     let results = got_files
         .iter()
         .map(|fi| match fi {
@@ -195,10 +360,10 @@ fn dvs_get_impl(files_string: Vec<String>, split_output: bool) -> Result<Robj> {
         .collect::<Vec<RFile>>();
 
     if !split_output {
-        Ok(results
+        with_warnings(results
             .into_dataframe()
             .map_err(|e| Error::Other(format!("Error converting added files to data frame: {e}")))?
-            .as_robj().clone())
+            .as_robj().clone(), warnings)
     }
     else {
         let failures = results
@@ -248,7 +413,7 @@ fn dvs_get_impl(files_string: Vec<String>, split_output: bool) -> Result<Robj> {
                 );
             }
 
-            Ok(List::from_hashmap(result).map_err(|e|Error::Other(format!("Error converting added files to data frame: {e}"))).into_robj())
+            with_warnings(List::from_hashmap(result).map_err(|e|Error::Other(format!("Error converting added files to data frame: {e}"))).into_robj(), warnings)
     }
 } // dvs_get_impl
 
@@ -263,6 +428,8 @@ struct RStatusFile {
     saved_by: Option<String>,
     message: Option<String>,
     absolute_path: Option<String>,
+    // joined with ", "
+    tags: Option<String>,
     error: Option<String>,
     error_message: Option<String>,
     input: Option<String>,
@@ -279,6 +446,7 @@ struct RStatusFileSuccess {
     message: String,
     blake3_checksum: String,
     absolute_path: String,
+    tags: String,
 }
 
 // error df
@@ -292,16 +460,49 @@ struct RStatusFileError {
 }
 
 #[extendr]
-fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
+fn dvs_status_impl(files: Vec<String>, tags: Vec<String>, annotation_names: Vec<String>, annotation_values: Vec<String>, members: bool, split_output: bool, progress: Nullable<Function>, workers: Nullable<f64>) -> Result<Robj> {
+    let filter = AnnotationFilter{tags, annotations: annotations_from_r(annotation_names, annotation_values)};
 
-    let status = status::status(&files).map_err(|e|
+    let status = with_progress(progress, |progress| status::status(&files, &filter, workers_from_r(workers), progress))?.map_err(|e|
         Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
     )?;
 
-    let results = status
+    // with members, each directory tracked as a unit is followed by a row for each of its files
+    let rows = status
         .iter()
-        .map(|fi| match fi {
-            Ok(fi) => RStatusFile{
+        .flat_map(|fi| {
+            let member_rows = match fi {
+                Ok(dir) if members => dir.members.iter().map(|member| (fi, Some(member))).collect(),
+                _ => Vec::new(),
+            };
+            std::iter::once((fi, None)).chain(member_rows)
+        })
+        .collect::<Vec<(&std::result::Result<status::FileStatus, FileError>, Option<&MemberStatus>)>>();
+
+    // annotations are returned as extra columns, NA for errors; a directory's files have its annotations
+    let annotations = rows
+        .iter()
+        .map(|(fi, _)| fi.as_ref().ok().map(|fi| &fi.annotations))
+        .collect::<Vec<Option<&BTreeMap<String, String>>>>();
+
+    let results = rows
+        .into_iter()
+        .map(|(fi, member)| match (fi, member) {
+            (Ok(fi), Some(member)) => RStatusFile{
+                relative_path: fi.relative_path.clone().map(|p| p.join(&member.path).to_string_lossy().to_string()),
+                status: member.status.outcome_to_string(),
+                size: Some(member.size),
+                add_time: Some(fi.add_time.clone()),
+                saved_by: Some(fi.saved_by.clone()),
+                message: Some(fi.message.clone()),
+                absolute_path: fi.absolute_path.clone().map(|p| p.join(&member.path).to_string_lossy().to_string()),
+                blake3_checksum: Some(member.blake3_checksum.clone()),
+                tags: Some(fi.tags.join(", ")),
+                error: None,
+                error_message: None,
+                input: None,
+            },
+            (Ok(fi), None) => RStatusFile{
                 relative_path: fi.relative_path.clone().map(|p| p.to_string_lossy().to_string()),
                 status: fi.status.outcome_to_string(),
                 size: Some(fi.size.clone()),
@@ -310,11 +511,12 @@ fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
                 message: Some(fi.message.clone()),
                 absolute_path: fi.absolute_path.clone().map(|p| p.to_string_lossy().to_string()),
                 blake3_checksum: Some(fi.blake3_checksum.clone()),
+                tags: Some(fi.tags.join(", ")),
                 error: None,
                 error_message: None,
                 input: None,
             },
-            Err(e) => RStatusFile{
+            (Err(e), _) => RStatusFile{
                 relative_path: e.relative_path.clone().map(|p| p.to_string_lossy().to_string()),
                 status: Status::Error.outcome_to_string(),
                 size: None,
@@ -323,6 +525,7 @@ fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
                 saved_by: None,
                 absolute_path: e.absolute_path.clone().map(|p| p.to_string_lossy().to_string()),
                 blake3_checksum: None,
+                tags: None,
                 error: Some(e.error.file_error_to_string()),
                 error_message: e.error_message.clone(),
                 input: Some(e.input.display().to_string())
@@ -331,10 +534,11 @@ fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
         .collect::<Vec<RStatusFile>>();
 
     if !split_output {
-        Ok(results
+        let df = results
             .into_dataframe()
             .map_err(|e| Error::Other(format!("Error converting added files to data frame: {e}")))?
-            .as_robj().clone())
+            .as_robj().clone();
+        with_annotation_columns(df, &annotations)
     }
     else {
         let failures = results
@@ -367,6 +571,7 @@ fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
                             size: res.size.unwrap(),
                             blake3_checksum: res.blake3_checksum.unwrap(),
                             absolute_path: res.absolute_path.unwrap(),
+                            tags: res.tags.unwrap(),
                         }
                     )
                 }
@@ -375,9 +580,10 @@ fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
 
             let mut result = HashMap::new();
             if successes.len() > 0 {
+                let success_annotations = annotations.into_iter().filter(|a| a.is_some()).collect();
                 result.insert(
                     "successes",
-                    successes.into_dataframe().unwrap().as_robj().clone(),
+                    with_annotation_columns(successes.into_dataframe().unwrap().as_robj().clone(), &success_annotations)?,
                 );
             }
             if failures.len() > 0 {
@@ -391,6 +597,40 @@ fn dvs_status_impl(files: Vec<String>, split_output: bool) -> Result<Robj> {
         }
 }
 
+// one column per annotation name after the columns of a data frame of files, NA for files without it
+fn with_annotation_columns(df: Robj, annotations: &Vec<Option<&BTreeMap<String, String>>>) -> Result<Robj> {
+    let names: BTreeSet<&String> = annotations.iter().flatten().flat_map(|a| a.keys()).collect();
+    if names.is_empty() {
+        return Ok(df)
+    }
+    let columns = names
+        .iter()
+        .map(|name| annotations.iter().map(|a| a.and_then(|a| a.get(*name).cloned())).collect::<Vec<Option<String>>>());
+    let columns = List::from_names_and_values(&names, columns)?;
+    let cbind = eval_string("cbind")?
+        .as_function()
+        .ok_or_else(|| Error::Other(String::from("cbind not found")))?;
+    cbind.call(pairlist!(df, columns))
+}
+
+// warnings for the R wrapper to raise with warn_from_result, as the dvs_warnings attribute of the result
+fn with_warnings(mut result: Robj, warnings: Vec<String>) -> Result<Robj> {
+    if !warnings.is_empty() {
+        result.set_attrib("dvs_warnings", warnings)?;
+    }
+    Ok(result)
+}
+
+// annotations are passed from R as names and values
+fn annotations_from_r(names: Vec<String>, values: Vec<String>) -> BTreeMap<String, String> {
+    names.into_iter().zip(values).collect()
+}
+
+// R numbers are doubles; anything below 1 is passed on as 0, which is refused as too few workers
+fn workers_from_r(workers: Nullable<f64>) -> Option<usize> {
+    <Option<f64>>::from(workers).map(|workers| workers.max(0.0) as usize)
+}
+
 // one df
 #[derive(Debug, IntoDataFrameRow, Clone)]
 struct RFileInfo {
@@ -551,12 +791,368 @@ fn is_explicit_path_impl(entry: String) -> bool {
 
 
 
+#[derive(Debug, IntoDataFrameRow)]
+struct RCheckedObject {
+    storage_path: String,
+    problem: String,
+    blake3_checksum: Option<String>,
+    actual_checksum: Option<String>,
+    size: Option<u64>,
+    message: String,
+    quarantine_path: Option<String>,
+}
+
+#[extendr]
+fn dvs_fsck_impl(referenced_only: bool, quarantine: bool) -> Result<Robj> {
+    let checked = fsck::fsck(referenced_only, quarantine).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    Ok(checked
+        .into_iter()
+        .map(|object| RCheckedObject{
+            storage_path: object.storage_path.display().to_string(),
+            problem: object.integrity.outcome_to_string(),
+            blake3_checksum: object.blake3_checksum,
+            actual_checksum: object.actual_checksum,
+            size: object.size,
+            message: object.message,
+            quarantine_path: object.quarantine_path.map(|p| p.display().to_string()),
+        })
+        .collect::<Vec<RCheckedObject>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting checked objects to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RUnreferencedObject {
+    storage_path: String,
+    blake3_checksum: String,
+    size: u64,
+    modification_time: Option<u64>,
+    reclaimable: bool,
+    deleted: bool,
+    error_message: Option<String>,
+}
+
+#[extendr]
+fn dvs_gc_impl(repos: Vec<String>, history: bool, grace_period: f64, delete: Vec<String>) -> Result<Robj> {
+    let repo_paths = repos.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+    let delete_paths = delete.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+    let unreferenced = gc::gc(&repo_paths, history, grace_period.max(0.0) as u64, &delete_paths).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    Ok(unreferenced
+        .into_iter()
+        .map(|object| RUnreferencedObject{
+            storage_path: object.storage_path.display().to_string(),
+            blake3_checksum: object.blake3_checksum,
+            size: object.size,
+            modification_time: object.modification_time
+                .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_secs()),
+            reclaimable: object.reclaimable,
+            deleted: object.deleted,
+            error_message: object.error_message,
+        })
+        .collect::<Vec<RUnreferencedObject>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting unreferenced objects to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RUsage {
+    grouping: String,
+    group: String,
+    files: u64,
+    objects: u64,
+    size: u64,
+    storage_size: u64,
+    missing: u64,
+}
+
+#[extendr]
+fn dvs_usage_impl(repos: Vec<String>) -> Result<Robj> {
+    let repo_paths = repos.iter().map(PathBuf::from).collect::<Vec<PathBuf>>();
+    let usage = usage::usage(&repo_paths).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    Ok(usage
+        .into_iter()
+        .map(|totals| RUsage{
+            grouping: totals.grouping,
+            group: totals.group,
+            files: totals.files,
+            objects: totals.objects,
+            size: totals.size,
+            storage_size: totals.storage_size,
+            missing: totals.missing,
+        })
+        .collect::<Vec<RUsage>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting usage to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RMigration {
+    storage_directory: String,
+    from_sharding_depth: u32,
+    to_sharding_depth: u32,
+    moved: u64,
+    skipped: u64,
+}
+
+#[extendr]
+fn dvs_migrate_layout_impl(sharding_depth: i32) -> Result<Robj> {
+    let migration = migrate_layout::migrate_layout(sharding_depth.max(0) as u32).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    let migration_df = RMigration{
+        storage_directory: migration.storage_directory.display().to_string(),
+        from_sharding_depth: migration.from_sharding_depth,
+        to_sharding_depth: migration.to_sharding_depth,
+        moved: migration.moved,
+        skipped: migration.skipped,
+    };
+
+    Ok(vec![migration_df]
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting migration to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RMigratedFile {
+    relative_path: String,
+    from_version: Option<u32>,
+    to_version: Option<u32>,
+    changes: Option<String>,
+    error_message: Option<String>,
+}
+
+#[extendr]
+fn dvs_migrate_metadata_impl() -> Result<Robj> {
+    let migrated = migrate_metadata::migrate_metadata().map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    Ok(migrated
+        .into_iter()
+        .map(|file| RMigratedFile{
+            relative_path: file.relative_path.display().to_string(),
+            from_version: file.from_version,
+            to_version: file.to_version,
+            changes: Some(file.changes.join("; ")).filter(|changes| !changes.is_empty()),
+            error_message: file.error_message,
+        })
+        .collect::<Vec<RMigratedFile>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting migrated files to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RIndexEntry {
+    blake3_checksum: String,
+    repo: String,
+    relative_path: String,
+    saved_by: String,
+    add_time: String,
+    message: String,
+}
+
+#[extendr]
+fn dvs_lookup_impl(hash: Nullable<&str>, path: Nullable<&str>) -> Result<Robj> {
+    let hash_in = <Option<&str>>::from(hash);
+    let path_in = <Option<&str>>::from(path).map(PathBuf::from);
+    let entries = lookup::lookup(hash_in, path_in.as_ref()).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    Ok(entries
+        .into_iter()
+        .map(|entry| RIndexEntry{
+            blake3_checksum: entry.blake3_checksum,
+            repo: entry.repo,
+            relative_path: entry.relative_path.display().to_string(),
+            saved_by: entry.saved_by,
+            add_time: entry.add_time,
+            message: entry.message,
+        })
+        .collect::<Vec<RIndexEntry>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting index entries to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RAnnotatedFile {
+    relative_path: Option<String>,
+    outcome: String,
+    blake3_checksum: Option<String>,
+    // joined with ", "
+    tags: Option<String>,
+    absolute_path: Option<String>,
+    input: Option<String>,
+    error: Option<String>,
+    error_message: Option<String>,
+}
+
+#[extendr]
+fn dvs_annotate_impl(files: Vec<String>, tags: Vec<String>, remove_tags: Vec<String>, annotation_names: Vec<String>, annotation_values: Vec<String>, remove_annotations: Vec<String>) -> Result<Robj> {
+    let edit = AnnotationEdit{
+        add_tags: tags,
+        remove_tags,
+        set: annotations_from_r(annotation_names, annotation_values),
+        remove: remove_annotations,
+    };
+    let annotated = annotate::annotate(&files, &edit).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    let df = annotated
+        .iter()
+        .map(|file| match file {
+            Ok(file) => RAnnotatedFile{
+                relative_path: file.relative_path.clone().map(|p| p.to_string_lossy().to_string()),
+                outcome: String::from(if file.changed {"annotated"} else {"present"}),
+                blake3_checksum: Some(file.blake3_checksum.clone()),
+                tags: Some(file.tags.join(", ")),
+                absolute_path: file.absolute_path.clone().map(|p| p.to_string_lossy().to_string()),
+                input: None,
+                error: None,
+                error_message: None,
+            },
+            Err(e) => RAnnotatedFile{
+                relative_path: e.relative_path.clone().map(|p| p.to_string_lossy().to_string()),
+                outcome: Outcome::Error.outcome_to_string(),
+                blake3_checksum: None,
+                tags: None,
+                absolute_path: e.absolute_path.clone().map(|p| p.to_string_lossy().to_string()),
+                input: Some(e.input.display().to_string()),
+                error: Some(e.error.file_error_to_string()),
+                error_message: e.error_message.clone(),
+            }
+        })
+        .collect::<Vec<RAnnotatedFile>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting annotated files to data frame: {e}")))?
+        .as_robj()
+        .clone();
+
+    let annotations = annotated
+        .iter()
+        .map(|file| file.as_ref().ok().map(|file| &file.annotations))
+        .collect::<Vec<Option<&BTreeMap<String, String>>>>();
+    with_annotation_columns(df, &annotations)
+}
+
+#[derive(Debug, IntoDataFrameRow)]
+struct RVerifiedDigest {
+    relative_path: Option<String>,
+    source: Option<String>,
+    algorithm: Option<String>,
+    outcome: String,
+    expected: Option<String>,
+    actual: Option<String>,
+    absolute_path: Option<String>,
+    input: Option<String>,
+    error: Option<String>,
+    error_message: Option<String>,
+}
+
+#[extendr]
+fn dvs_verify_impl(files: Vec<String>, algorithms: Vec<String>, from_storage: bool, progress: Nullable<Function>, workers: Nullable<f64>) -> Result<Robj> {
+    let algorithms_in = algorithms
+        .iter()
+        .map(|name| config::parse_digest_algorithm(name))
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .map_err(|e| Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message)))?;
+
+    let verified = with_progress(progress, |progress| verify::verify(&files, &algorithms_in, from_storage, workers_from_r(workers), progress))?.map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )?;
+
+    // a row per digest checked, or per file that couldn't be checked
+    Ok(verified
+        .into_iter()
+        .flat_map(|file| match file {
+            Ok(digests) => digests
+                .into_iter()
+                .map(|digest| RVerifiedDigest{
+                    relative_path: digest.relative_path.map(|p| p.to_string_lossy().to_string()),
+                    source: Some(digest.source),
+                    algorithm: Some(digest.algorithm),
+                    outcome: digest.outcome.outcome_to_string(),
+                    expected: Some(digest.expected),
+                    actual: Some(digest.actual),
+                    absolute_path: digest.absolute_path.map(|p| p.to_string_lossy().to_string()),
+                    input: None,
+                    error: None,
+                    error_message: None,
+                })
+                .collect::<Vec<RVerifiedDigest>>(),
+            Err(e) => vec![RVerifiedDigest{
+                relative_path: e.relative_path.map(|p| p.to_string_lossy().to_string()),
+                source: None,
+                algorithm: None,
+                outcome: Outcome::Error.outcome_to_string(),
+                expected: None,
+                actual: None,
+                absolute_path: e.absolute_path.map(|p| p.to_string_lossy().to_string()),
+                input: Some(e.input.display().to_string()),
+                error: Some(e.error.file_error_to_string()),
+                error_message: e.error_message,
+            }],
+        })
+        .collect::<Vec<RVerifiedDigest>>()
+        .into_dataframe()
+        .map_err(|e| Error::Other(format!("Error converting verified digests to data frame: {e}")))?
+        .as_robj()
+        .clone())
+}
+
+#[extendr]
+fn dvs_serve_impl(address: &str, token: Nullable<&str>, max_upload_size: f64) -> Result<()> {
+    let token_in = <Option<&str>>::from(token);
+    // requests are logged with message(), and the server stops when the user interrupts R
+    serve::serve(address, token_in, max_upload_size.max(0.0) as u64, |lines| {
+        for line in lines {
+            let _ = call!("message", line);
+        }
+        !user_interrupted()
+    }).map_err(|e|
+        Error::Other(format!("{}: {}", e.error.batch_error_to_string(), e.error_message))
+    )
+}
+
 extendr_module! {
     mod dvs;
     fn dvs_init_impl;
     fn dvs_add_impl;
     fn dvs_get_impl;
     fn dvs_status_impl;
+    fn dvs_serve_impl;
+    fn dvs_fsck_impl;
+    fn dvs_gc_impl;
+    fn dvs_usage_impl;
+    fn dvs_migrate_layout_impl;
+    fn dvs_lookup_impl;
+    fn dvs_migrate_metadata_impl;
+    fn dvs_annotate_impl;
+    fn dvs_verify_impl;
     fn get_file_info_impl;
     fn parse_files_from_globs_add_impl;
     fn parse_files_from_globs_get_impl;
@@ -1,7 +1,6 @@
-use crate::helpers::{config, copy, error::{BatchError, BatchErrorType, FileError}, file, hash, ignore, outcome::Outcome, repo};
-use std::{fs, path::PathBuf, u32};
+use crate::helpers::{annotation::AnnotationEdit, batch, config::{self, ChunkingConfig}, directory::{self, Member}, error::{BatchError, BatchErrorType, FileError, FileErrorType}, file, hash::{self, DigestAlgorithm}, ignore, index::{Index, IndexEntry}, outcome::Outcome, progress::{self, Progress}, repo, storage::{self, Location, ObjectFormat}};
+use std::{collections::BTreeMap, fs, path::PathBuf, sync::Arc};
 use chrono:: Utc;
-use file_owner::Group;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct AddedFile {
@@ -10,23 +9,54 @@ pub struct AddedFile {
     pub size: u64,
     pub blake3_checksum: String,
     pub absolute_path: PathBuf,
+    // outcome of the copy to each storage location, primary first; empty if the file was already added
+    pub replicas: Vec<ReplicaOutcome>,
+    // why the version added couldn't be recorded in the storage directory's index; the add itself succeeded
+    pub index_error: Option<String>,
 }
 
-pub fn add(files: &Vec<PathBuf>, message_in: Option<&str>, strict: bool) -> std::result::Result<Vec<std::result::Result<AddedFile, FileError>>, BatchError> {
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicaOutcome {
+    pub location: String,
+    pub outcome: Outcome,
+    pub error_message: Option<String>,
+}
+
+// annotations are applied to each file, whether or not it was already added; workers overrides the ones in dvs.yaml;
+// progress, if given, is reported to as files are hashed and copied, and can cancel the batch
+pub fn add(files: &Vec<PathBuf>, message_in: Option<&str>, strict: bool, annotations: &AnnotationEdit, workers: Option<usize>, progress: Option<&Arc<Progress>>) -> std::result::Result<Vec<std::result::Result<AddedFile, FileError>>, BatchError> {
     // Get git root
     let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
 
     // load the config
     let conf = config::read(&git_dir)?;
 
-    // get group, check if specified
-    let group = config::get_group(&conf.group.unwrap_or_default())?;
-        
-    // check storage directory exists
-    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+    // check tags and annotation names
+    annotations.check()?;
+
+    // set up the storage locations (checks group, storage directories, and permissions for local storage)
+    let mut locations = storage::locations_from_config(&conf);
+
+    // compress and/or encrypt new objects as set in dvs.yaml, loading the key if there is one
+    let format = storage::object_format(&conf)?;
+
+    // check chunk sizes if chunking is set in dvs.yaml
+    let chunking = config::get_chunking(&conf.chunking)?;
+
+    // files added at once
+    let workers = config::get_workers(workers, &conf.workers)?;
 
-    // get file permissions
-    let permissions = config::get_mode_u32(&conf.permissions.unwrap_or(664))?;
+    // digests recorded besides the blake3 hash, which is always recorded as blake3_checksum
+    let digests: Vec<DigestAlgorithm> = conf.digests.iter().filter(|algorithm| **algorithm != DigestAlgorithm::Blake3).copied().collect();
+
+    // each version added is recorded in the index of the storage directory, if it's local
+    let index = Index::from_config(&conf);
+    let repo = repo::repo_identity(&git_dir);
+
+    // error if a required location can't be reached; optional ones are reported per file
+    if let Some(i) = locations.iter().position(|location| location.required && location.backend.is_err()) {
+        locations.swap_remove(i).backend?;
+    }
 
     // collect paths out of input - sort through globs/explicitly-named files
     //let queued_paths = parse::parse_files_from_globs_add(&globs);
@@ -52,33 +82,76 @@ pub fn add(files: &Vec<PathBuf>, message_in: Option<&str>, strict: bool) -> std:
         else {String::from("")}
     };
 
-    Ok(files.into_iter().map(|file| {
-        add_file(&file, &git_dir, &group, &storage_dir, &permissions, &message, strict)
-    }).collect::<Vec<std::result::Result<AddedFile, FileError>>>())
+    // add in parallel; results keep the order of files
+    batch::map_files(files, workers, progress, |file| {
+        let mut added = add_file(&file, &git_dir, &locations, &format, &chunking, &message, annotations, conf.record_attributes, &digests, strict)?;
+        // files that were already added and current have no replicas, and were recorded when they were added
+        if let Some(index) = index.as_ref().filter(|_| !added.replicas.is_empty()) {
+            added.index_error = record(index, &repo, &git_dir, file).err().map(|e| e.to_string());
+        }
+        Ok(added)
+    })
+}
+
+// the file is in storage and its metadata written by now, so a failure to record it is reported without failing the add
+fn record(index: &Index, repo: &String, git_dir: &PathBuf, local_path: &PathBuf) -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let metadata = file::load(local_path)?;
+    let relative_path = repo::get_relative_path(git_dir, local_path)?;
+    index.record(&IndexEntry{
+        blake3_checksum: metadata.blake3_checksum,
+        repo: repo.clone(),
+        relative_path,
+        saved_by: metadata.saved_by,
+        add_time: metadata.add_time,
+        message: metadata.message,
+    })
 }
 
-fn add_file(local_path: &PathBuf, git_dir: &PathBuf, group: &Option<Group>, storage_dir: &PathBuf, permissions: &u32, message: &String, strict: bool) -> std::result::Result<AddedFile, FileError> {
+fn add_file(local_path: &PathBuf, git_dir: &PathBuf, locations: &Vec<Location>, format: &ObjectFormat, chunking: &Option<ChunkingConfig>, message: &String, annotations: &AnnotationEdit, record_attributes: bool, digests: &Vec<DigestAlgorithm>, strict: bool) -> std::result::Result<AddedFile, FileError> {
     // get absolute path
     let absolute_path = file::get_absolute_path(local_path)?;
 
     // get relative path
     let relative_path = repo::get_relative_path_to_wd(local_path)?;
 
-    // error if file is a directory
-    file::check_if_dir(local_path)?;
+    // a directory is added as a unit: its files are stored by their own hashes, and it's recorded by a tree hash over them
+    let members = if local_path.is_dir() {Some(directory::get_members(local_path)?)} else {None};
 
     // get file hash
-    let blake3_checksum = hash::get_file_hash(local_path)?;
+    let blake3_checksum = match &members {
+        Some(members) => directory::tree_hash(members),
+        None => hash::get_file_hash(local_path)?,
+    };
 
     // if file already added and current, no-op
-    if let Ok(metadata) = file::load(local_path) { // check if already added
+    let loaded = file::load(local_path).ok();
+    if let Some(metadata) = &loaded { // check if already added
         if blake3_checksum == metadata.blake3_checksum { // check if current
+            // still update its annotations, and record its attributes and digests if it was added before they were recorded;
+            // neither is recorded for directories
+            let mut metadata = metadata.clone();
+            let mut changed = annotations.apply(&mut metadata);
+            if record_attributes && metadata.mtime.is_none() && members.is_none() {
+                let (mtime, mode) = file::get_file_attributes(local_path)?;
+                (metadata.mtime, metadata.mode) = (Some(mtime), Some(mode));
+                changed = true;
+            }
+            let missing_digests: Vec<DigestAlgorithm> = digests.iter().filter(|algorithm| !metadata.digests.contains_key(&algorithm.algorithm_to_string())).copied().collect();
+            if !missing_digests.is_empty() && members.is_none() {
+                metadata.digests.extend(hash::get_file_digests(local_path, &missing_digests)?);
+                changed = true;
+            }
+            if changed {
+                file::save(&metadata, local_path)?;
+            }
             return Ok(AddedFile { // no-op
                 relative_path: relative_path.clone(),
                 absolute_path: absolute_path.clone(),
                 outcome: Outcome::Present,
                 size: metadata.size,
-                blake3_checksum: metadata.blake3_checksum,
+                blake3_checksum: metadata.blake3_checksum.clone(),
+                replicas: Vec::new(),
+                index_error: None,
             });
         }
     }
@@ -87,20 +160,49 @@ fn add_file(local_path: &PathBuf, git_dir: &PathBuf, group: &Option<Group>, stor
     // check if file in git repo
     repo::check_file_in_git_repo(local_path, git_dir)?;
 
-    // get file size
-    let file_size_bytes = file::get_file_size(local_path)?; // [MAN-ADD-003]
+    // get file size, the total of its files for a directory
+    let file_size_bytes = match &members {
+        Some(members) => members.iter().map(|member| member.size).sum(),
+        None => file::get_file_size(local_path)?, // [MAN-ADD-003]
+    };
 
     // get user name
     let user_name: String = file::get_user_name(local_path)?; // [MAN-ADD-002]
 
-    // create metadata
-    let metadata = file::Metadata{
+    // get modification time and mode if they're recorded
+    let (mtime, mode) =
+        if record_attributes && members.is_none() {
+            let (mtime, mode) = file::get_file_attributes(local_path)?;
+            (Some(mtime), Some(mode))
+        }
+        else {(None, None)};
+
+    // get the digests set in dvs.yaml
+    let file_digests =
+        if digests.is_empty() || members.is_some() {BTreeMap::new()}
+        else {hash::get_file_digests(local_path, digests)?};
+
+    // create metadata in the version this dvs writes; tags and annotations are carried over, but fields written by a
+    // newer version of dvs described the previous contents, so they're dropped with it
+    let mut metadata = file::Metadata{
+        metadata_version: file::METADATA_VERSION,
         blake3_checksum: blake3_checksum.clone(),
         size: file_size_bytes,
         add_time: Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ").to_string(),
         message: message.clone(),
-        saved_by: user_name
+        saved_by: user_name,
+        tags: loaded.as_ref().map(|previous| previous.tags.clone()).unwrap_or_default(),
+        annotations: loaded.as_ref().map(|previous| previous.annotations.clone()).unwrap_or_default(),
+        mtime,
+        mode,
+        digests: file_digests,
+        members: members.clone(),
+        extra: serde_json::Map::new(),
     };
+    annotations.apply(&mut metadata);
+
+    // keep the metadata of the previous version, so it can be put back if the copy fails
+    let previous_metadata = fs::read(file::metadata_path(local_path)).ok();
 
     // write metadata file
     file::save(&metadata, local_path)?;
@@ -108,21 +210,50 @@ fn add_file(local_path: &PathBuf, git_dir: &PathBuf, group: &Option<Group>, stor
     // Add file to gitignore
     ignore::add_gitignore_entry(local_path)?;
     
-    // get storage path
-    let storage_path = hash::get_storage_path(storage_dir, &blake3_checksum);
-    
-    // copy
-    let outcome = 
-        if !storage_path.exists() { // if not already copied
-            if let Err(e) = copy::copy_file_to_storage_directory(local_path, &storage_path, permissions, group) {
-                if strict {
-                    // remove metadata file
-                    let _ = fs::remove_file(file::metadata_path(local_path));
-                    // remove copied file from storage directory
-                    let _ = fs::remove_file(storage_path);
+    // copy to each storage location
+    let mut replicas: Vec<ReplicaOutcome> = Vec::new();
+    for location in locations {
+        let copied = match &members {
+            Some(members) => copy_members_to_location(location, local_path, members, format, chunking.as_ref()),
+            // large files are stored in chunks if set in dvs.yaml
+            None => copy_to_location(location, local_path, &blake3_checksum, format, chunking.as_ref().filter(|chunking| file_size_bytes >= chunking.min_file_size)),
+        };
+        match copied {
+            Ok(outcome) => replicas.push(ReplicaOutcome{
+                location: location.name.clone(),
+                outcome,
+                error_message: None
+            }),
+            // optional replicas can be caught up later
+            Err(e) if !location.required => replicas.push(ReplicaOutcome{
+                location: location.name.clone(),
+                outcome: Outcome::Error,
+                error_message: e.error_message
+            }),
+            Err(e) => {
+                // a cancelled file is always undone, so its metadata doesn't point to an object that isn't in storage
+                if strict || progress::is_cancelled() {
+                    // put back the previous metadata file, or remove it
+                    match &previous_metadata {
+                        Some(contents) => {let _ = fs::write(file::metadata_path(local_path), contents);}
+                        None => {let _ = fs::remove_file(file::metadata_path(local_path));}
+                    }
+                    // remove the copies this add made from storage, and only those: the location that failed writes nothing in place,
+                    // and objects that were already present may be used by other files; a directory's are left for gc, since other versions may share them
+                    let copied = locations.iter().zip(&replicas).filter(|(_, replica)| replica.outcome == Outcome::Copied).map(|(location, _)| location);
+                    for location in copied {
+                        if let Ok(backend) = &location.backend {
+                            let _ = storage::delete(backend.as_ref(), &blake3_checksum);
+                        }
+                    }
                 }
                 return Err(e)
-            };
+            }
+        }
+    }
+
+    let outcome =
+        if replicas.iter().any(|replica| replica.outcome == Outcome::Copied) {
             Outcome::Copied
         }
         else {
@@ -134,9 +265,42 @@ fn add_file(local_path: &PathBuf, git_dir: &PathBuf, group: &Option<Group>, stor
             absolute_path,
             outcome,
             size: file_size_bytes,
-            blake3_checksum
+            blake3_checksum,
+            replicas,
+            index_error: None,
         }
     )
 }
 
+fn copy_to_location(location: &Location, local_path: &PathBuf, blake3_checksum: &String, format: &ObjectFormat, chunking: Option<&ChunkingConfig>) -> std::result::Result<Outcome, FileError> {
+    let backend = location.backend.as_ref().map_err(|e|
+        FileError{
+            relative_path: file::try_to_get_rel_path(local_path),
+            absolute_path: file::try_to_get_abs_path(local_path),
+            error: FileErrorType::StorageNotReachable,
+            error_message: Some(format!("{}: {}", location.name, e.error_message)),
+            input: local_path.clone()
+        }
+    )?;
 
+    if storage::exists(backend.as_ref(), blake3_checksum, local_path)? && storage::intact(backend.as_ref(), blake3_checksum) { // already copied
+        // it may have been unreferenced long enough for gc to delete it
+        storage::touch(backend.as_ref(), blake3_checksum);
+        return Ok(Outcome::Present)
+    }
+    // Present if another add of the same file got there first; a corrupt object is replaced
+    match chunking {
+        Some(chunking) => storage::put_chunked(backend.as_ref(), local_path, blake3_checksum, format, chunking),
+        None => storage::put(backend.as_ref(), local_path, blake3_checksum, format),
+    }
+}
+
+// copies each file of a directory to the location as an object of its own; Copied if any of them weren't there
+fn copy_members_to_location(location: &Location, local_path: &PathBuf, members: &Vec<Member>, format: &ObjectFormat, chunking: Option<&ChunkingConfig>) -> std::result::Result<Outcome, FileError> {
+    // in parallel, on the threads of the batch the directory is part of
+    let outcomes = batch::map_within(members, |member| {
+        let chunking = chunking.filter(|chunking| member.size >= chunking.min_file_size);
+        copy_to_location(location, &directory::member_path(local_path, member)?, &member.blake3_checksum, format, chunking)
+    }).into_iter().collect::<std::result::Result<Vec<Outcome>, FileError>>()?;
+    Ok(if outcomes.contains(&Outcome::Copied) {Outcome::Copied} else {Outcome::Present})
+}
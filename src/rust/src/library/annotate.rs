@@ -0,0 +1,57 @@
+use crate::helpers::{annotation::AnnotationEdit, error::{BatchError, FileError, FileErrorType}, file, repo};
+use std::{collections::BTreeMap, path::PathBuf};
+
+#[derive(PartialEq, Debug)]
+pub struct AnnotatedFile {
+    pub relative_path: Option<PathBuf>,
+    pub absolute_path: Option<PathBuf>,
+    pub blake3_checksum: String,
+    // after the edit
+    pub tags: Vec<String>,
+    pub annotations: BTreeMap<String, String>,
+    // false if the file already had the tags and annotations given
+    pub changed: bool,
+}
+
+// edits the tags and annotations of added files; only their .dvs files are changed,
+// so the files themselves don't need to be present
+pub fn annotate(files: &Vec<String>, edit: &AnnotationEdit) -> std::result::Result<Vec<std::result::Result<AnnotatedFile, FileError>>, BatchError> {
+    // Get git root
+    repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // check tags and annotation names
+    edit.check()?;
+
+    Ok(files.iter().map(|path| annotate_file(&PathBuf::from(path), edit)).collect())
+}
+
+fn annotate_file(local_path: &PathBuf, edit: &AnnotationEdit) -> std::result::Result<AnnotatedFile, FileError> {
+    let relative_path = file::try_to_get_rel_path(local_path);
+    let absolute_path = file::try_to_get_abs_path(local_path);
+
+    // check if metadata file exists
+    if !file::metadata_path(local_path).exists() {
+        return Err(FileError{
+            relative_path,
+            absolute_path,
+            error: FileErrorType::FileNotAdded,
+            error_message: Some(String::from("metadata file not found - add the file to dvs to annotate it")),
+            input: local_path.clone()
+        })
+    }
+
+    let mut metadata = file::load(local_path)?;
+    let changed = edit.apply(&mut metadata);
+    if changed {
+        file::save(&metadata, local_path)?;
+    }
+
+    Ok(AnnotatedFile{
+        relative_path,
+        absolute_path,
+        blake3_checksum: metadata.blake3_checksum,
+        tags: metadata.tags,
+        annotations: metadata.annotations,
+        changed,
+    })
+}
@@ -0,0 +1,278 @@
+use crate::helpers::{chunk::{self, Manifest}, compress, config, encrypt, error::{BatchError, BatchErrorType}, file, hash, layout::{self, Layout}, outcome::Integrity, parse, repo, storage};
+use std::{collections::HashMap, fs::{self, File}, io::{self, Read}, path::{Path, PathBuf}};
+use rayon::prelude::*;
+
+// bad objects are moved here, keeping their path relative to the storage directory
+pub const QUARANTINE_DIR: &str = ".quarantine";
+
+#[derive(Debug)]
+pub struct CheckedObject {
+    pub storage_path: PathBuf,
+    pub integrity: Integrity,
+    // hash the object is named by
+    pub blake3_checksum: Option<String>,
+    // hash of what it holds
+    pub actual_checksum: Option<String>,
+    pub size: Option<u64>,
+    pub message: String,
+    pub quarantine_path: Option<PathBuf>,
+}
+
+// what an object holds, from its name
+enum Kind {
+    Raw,
+    Zstd,
+    Encrypted,
+    Manifest,
+}
+
+// checks the objects in the storage directory against their names, returning the ones with problems
+pub fn fsck(referenced_only: bool, quarantine: bool) -> std::result::Result<Vec<CheckedObject>, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // check storage directory exists
+    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+
+    // get layout
+    let layout = layout::read(&storage_dir)?;
+
+    // sizes of the files the project's .dvs files refer to and of the chunks they're stored in
+    let expected_sizes = referenced_sizes(&storage_dir, &layout)?;
+
+    // referenced files and chunks with no object at all
+    let mut missing: Vec<CheckedObject> = expected_sizes
+        .iter()
+        .filter(|(hash, _)| object_paths(&storage_dir, &layout, hash).is_empty())
+        .map(|(hash, size)| CheckedObject{
+            storage_path: layout.storage_path(&storage_dir, hash),
+            integrity: Integrity::Missing,
+            blake3_checksum: Some(hash.clone()),
+            actual_checksum: None,
+            size: Some(*size),
+            message: String::from("referenced by the project but not in storage"),
+            quarantine_path: None,
+        })
+        .collect();
+    missing.sort_by(|a, b| a.storage_path.cmp(&b.storage_path));
+
+    let storage_paths: Vec<PathBuf> =
+        if referenced_only {
+            let mut paths: Vec<PathBuf> = expected_sizes.keys().flat_map(|hash| object_paths(&storage_dir, &layout, hash)).collect();
+            paths.sort();
+            paths
+        }
+        else {
+            storage::walk_storage_dir(&storage_dir).map_err(|e|
+                BatchError{
+                    error: BatchErrorType::StorageDirNotFound,
+                    error_message: format!("could not read storage_dir: {}, {e}", storage_dir.display())
+                }
+            )?
+        };
+
+    // re-hash in parallel; order is kept
+    let mut problems: Vec<CheckedObject> = storage_paths
+        .par_iter()
+        .map(|storage_path| check_object(&storage_dir, &layout, storage_path, &expected_sizes))
+        .filter(|checked| checked.integrity != Integrity::Ok)
+        .collect();
+
+    if quarantine {
+        // objects that couldn't be checked may well be fine
+        for checked in problems.iter_mut().filter(|checked| checked.integrity != Integrity::Unchecked) {
+            quarantine_object(&storage_dir, checked);
+        }
+    }
+
+    problems.append(&mut missing);
+    Ok(problems)
+}
+
+// hashes and sizes of the files referenced by the project's .dvs files and of their chunks
+fn referenced_sizes(storage_dir: &PathBuf, layout: &Layout) -> std::result::Result<HashMap<String, u64>, BatchError> {
+    let mut sizes: HashMap<String, u64> = HashMap::new();
+    for path in parse::get_all_meta_files()? {
+        // unreadable metadata is reported by dvs_status
+        if let Ok(metadata) = file::load(&path) {
+            // a directory refers to the objects of its files
+            for (hash, size) in metadata.objects() {
+                let manifest_path = layout.storage_path(storage_dir, &chunk::manifest_key(&hash));
+                if let Ok(manifest) = read_manifest(&manifest_path) {
+                    for chunk in manifest.chunks {
+                        sizes.insert(chunk.blake3_checksum, chunk.size);
+                    }
+                }
+                sizes.insert(hash, size);
+            }
+        }
+    }
+    Ok(sizes)
+}
+
+// the objects stored for a hash: raw, compressed, encrypted, and/or a chunk manifest
+fn object_paths(storage_dir: &PathBuf, layout: &Layout, hash: &String) -> Vec<PathBuf> {
+    [hash.clone(), compress::zstd_key(hash), encrypt::encrypted_key(hash), chunk::manifest_key(hash)]
+        .iter()
+        .map(|key| layout.storage_path(storage_dir, key))
+        .filter(|path| path.is_file())
+        .collect()
+}
+
+// <hash[..2]>/<hash[2..]>[.zst|.enc|.chunks] -> key, hash and kind
+fn parse_name(rel_path: &Path) -> Option<(String, String, Kind)> {
+    let key = storage::parse_storage_path(rel_path)?;
+    let kind =
+        if compress::is_zstd_key(&key) {Kind::Zstd}
+        else if encrypt::is_encrypted_key(&key) {Kind::Encrypted}
+        else if chunk::is_manifest_key(&key) {Kind::Manifest}
+        else {Kind::Raw};
+    Some((storage::key_hash(&key), key, kind))
+}
+
+fn check_object(storage_dir: &PathBuf, layout: &Layout, storage_path: &PathBuf, expected_sizes: &HashMap<String, u64>) -> CheckedObject {
+    let mut checked = CheckedObject{
+        storage_path: storage_path.clone(),
+        integrity: Integrity::Ok,
+        blake3_checksum: None,
+        actual_checksum: None,
+        size: storage_path.metadata().ok().map(|metadata| metadata.len()),
+        message: String::new(),
+        quarantine_path: None,
+    };
+
+    let rel_path = storage_path.strip_prefix(storage_dir).unwrap_or(storage_path);
+    let (hash, key, kind) = match parse_name(rel_path) {
+        Some(parsed) => parsed,
+        None => {
+            checked.integrity = Integrity::Stray;
+            checked.message = String::from("not named like an object");
+            return checked
+        }
+    };
+    checked.blake3_checksum = Some(hash.clone());
+
+    // sharded to another depth than the layout's, so it's never read
+    let depths = [Some(layout.sharding_depth), layout.migrating_to];
+    if !depths.iter().flatten().any(|depth| layout::sharded_path(storage_dir, &key, *depth) == *storage_path) {
+        checked.integrity = Integrity::Misnamed;
+        checked.message = format!("belongs at {}", layout.storage_path(storage_dir, &key).display());
+        return checked
+    }
+
+    // hash of the contents and how many bytes they are, uncompressed and decrypted
+    let contents = match kind {
+        Kind::Raw => hash::hash_file_with_blake3(storage_path)
+            .map(|actual| (actual.unwrap_or_default(), checked.size.unwrap_or_default())),
+        Kind::Zstd => hash_zstd(storage_path),
+        Kind::Encrypted => match open_encrypted(storage_path, &hash) {
+            Ok(Some(mut decoder)) => hash_reader(&mut decoder),
+            Ok(None) => {
+                checked.integrity = Integrity::Unchecked;
+                checked.message = String::from("encryption key not available, so the contents were not checked");
+                return checked
+            }
+            Err(e) => {
+                checked.integrity = Integrity::Corrupt;
+                checked.message = format!("could not read contents: {e}");
+                return checked
+            }
+        },
+        Kind::Manifest => {
+            check_manifest(storage_path, &hash, &mut checked);
+            return checked
+        }
+    };
+
+    let (actual_hash, actual_size) = match contents {
+        Ok(contents) => contents,
+        Err(e) => {
+            checked.integrity = if e.kind() == io::ErrorKind::UnexpectedEof {Integrity::Truncated} else {Integrity::Corrupt};
+            checked.message = format!("could not read contents: {e}");
+            return checked
+        }
+    };
+    checked.actual_checksum = Some(actual_hash.clone());
+
+    if actual_hash == hash {
+        return checked
+    }
+    match expected_sizes.get(&hash) {
+        Some(expected_size) if actual_size < *expected_size => {
+            checked.integrity = Integrity::Truncated;
+            checked.message = format!("{actual_size} of {expected_size} bytes");
+        }
+        _ if expected_sizes.contains_key(&actual_hash) => {
+            checked.integrity = Integrity::Misnamed;
+            checked.message = format!("contents belong at {}", layout.storage_path(storage_dir, &actual_hash).display());
+        }
+        _ => {
+            checked.integrity = Integrity::Corrupt;
+            checked.message = String::from("contents do not match their hash");
+        }
+    }
+    checked
+}
+
+fn hash_zstd(storage_path: &PathBuf) -> io::Result<(String, u64)> {
+    hash_reader(&mut zstd::stream::read::Decoder::new(File::open(storage_path)?)?)
+}
+
+fn hash_reader(reader: &mut dyn Read) -> io::Result<(String, u64)> {
+    let mut hasher = blake3::Hasher::new();
+    let size = io::copy(reader, &mut hasher)?;
+    Ok((hasher.finalize().to_string(), size))
+}
+
+// the decrypted contents of an encrypted object, or None if its key isn't available here
+fn open_encrypted(storage_path: &PathBuf, hash: &String) -> std::result::Result<Option<Box<dyn Read>>, Box<dyn std::error::Error>> {
+    let key_id = encrypt::key_id(&mut File::open(storage_path)?)?;
+    if encrypt::load_key(&key_id).is_err() {
+        return Ok(None)
+    }
+    Ok(Some(encrypt::decoder(Box::new(File::open(storage_path)?), hash)?))
+}
+
+fn read_manifest(manifest_path: &PathBuf) -> std::result::Result<Manifest, Box<dyn std::error::Error>> {
+    Ok(serde_json::from_str(&fs::read_to_string(manifest_path)?)?)
+}
+
+// manifests can't be re-hashed; check that they parse and are for the file they're named by
+fn check_manifest(storage_path: &PathBuf, hash: &String, checked: &mut CheckedObject) {
+    match read_manifest(storage_path) {
+        Err(e) => {
+            checked.integrity = Integrity::Corrupt;
+            checked.message = format!("could not parse manifest: {e}");
+        }
+        Ok(manifest) => {
+            checked.actual_checksum = Some(manifest.blake3_checksum.clone());
+            if manifest.blake3_checksum != *hash {
+                checked.integrity = Integrity::Misnamed;
+                checked.message = format!("manifest is for {}", manifest.blake3_checksum);
+            }
+            else if manifest.chunks.iter().map(|chunk| chunk.size).sum::<u64>() != manifest.size {
+                checked.integrity = Integrity::Corrupt;
+                checked.message = String::from("chunk sizes do not add up to the file size");
+            }
+        }
+    }
+}
+
+fn quarantine_object(storage_dir: &PathBuf, checked: &mut CheckedObject) {
+    let rel_path = match checked.storage_path.strip_prefix(storage_dir) {
+        Ok(rel_path) => rel_path.to_path_buf(),
+        Err(_) => return,
+    };
+    let quarantine_path = storage_dir.join(QUARANTINE_DIR).join(rel_path);
+    let result = quarantine_path
+        .parent()
+        .map_or(Ok(()), |parent| fs::create_dir_all(parent))
+        .and_then(|_| fs::rename(&checked.storage_path, &quarantine_path));
+    match result {
+        Ok(_) => checked.quarantine_path = Some(quarantine_path),
+        Err(e) => checked.message = format!("{}; could not quarantine: {e}", checked.message),
+    }
+}
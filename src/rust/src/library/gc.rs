@@ -0,0 +1,133 @@
+use crate::helpers::{chunk::{self, Manifest}, config, error::{BatchError, BatchErrorType}, file, layout, parse, repo, storage};
+use std::{collections::HashSet, fs, path::PathBuf, time::{Duration, SystemTime}};
+
+#[derive(Debug)]
+pub struct UnreferencedObject {
+    pub storage_path: PathBuf,
+    pub blake3_checksum: String,
+    pub size: u64,
+    pub modification_time: Option<SystemTime>,
+    // older than the grace period, so it can be deleted
+    pub reclaimable: bool,
+    pub deleted: bool,
+    pub error_message: Option<String>,
+}
+
+// finds the objects in the storage directory that no .dvs file in the given repositories refers to,
+// and deletes the ones at the given storage paths, e.g. those reported reclaimable by an earlier call
+// and confirmed, if they still are
+pub fn gc(repos: &Vec<PathBuf>, history: bool, grace_days: u64, delete: &Vec<PathBuf>) -> std::result::Result<Vec<UnreferencedObject>, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // check storage directory exists
+    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+
+    // get layout
+    let layout = layout::read(&storage_dir)?;
+
+    // the current project if no repositories are given
+    let repo_dirs = if repos.is_empty() {
+        vec![git_dir]
+    }
+    else {
+        repos.iter().map(|repo| repo::get_nearest_repo_dir(repo)).collect::<std::result::Result<Vec<PathBuf>, BatchError>>()?
+    };
+
+    // anything that can't be read stops gc, since its objects would look unreferenced
+    let mut referenced: HashSet<String> = HashSet::new();
+    for repo_dir in &repo_dirs {
+        for path in parse::get_meta_files_in(repo_dir) {
+            let metadata = file::load(&path).map_err(|e| references_error(format!("{}: {}", file::metadata_path(&path).display(), e)))?;
+            referenced.extend(metadata.objects().into_iter().map(|(hash, _)| hash));
+        }
+        if history {
+            for contents in repo::metadata_in_history(repo_dir).map_err(|e| references_error(e.to_string()))? {
+                // read only the hashes, so metadata written by any version of dvs counts
+                let metadata: serde_json::Value = serde_json::from_str(&contents).map_err(|e|
+                    references_error(format!(".dvs file in the history of {}: {e}", repo_dir.display()))
+                )?;
+                if let Some(hash) = metadata.get("blake3_checksum").and_then(|hash| hash.as_str()) {
+                    referenced.insert(hash.to_string());
+                }
+                // and those of the files of a directory
+                for member in metadata.get("members").and_then(|members| members.as_array()).into_iter().flatten() {
+                    if let Some(hash) = member.get("blake3_checksum").and_then(|hash| hash.as_str()) {
+                        referenced.insert(hash.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    // chunks of referenced chunked files
+    let mut chunks: HashSet<String> = HashSet::new();
+    for hash in &referenced {
+        let manifest_path = layout.storage_path(&storage_dir, &chunk::manifest_key(hash));
+        if manifest_path.exists() {
+            let manifest: Manifest = fs::read_to_string(&manifest_path)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| serde_json::from_str(&contents).map_err(|e| e.to_string()))
+                .map_err(|e| references_error(format!("{}: {e}", manifest_path.display())))?;
+            chunks.extend(manifest.chunks.into_iter().map(|chunk| chunk.blake3_checksum));
+        }
+    }
+    referenced.extend(chunks);
+
+    let storage_paths = storage::walk_storage_dir(&storage_dir).map_err(|e|
+        BatchError{
+            error: BatchErrorType::StorageDirNotFound,
+            error_message: format!("could not read storage_dir: {}, {e}", storage_dir.display())
+        }
+    )?;
+
+    // objects modified before this are past the grace period
+    let cutoff = SystemTime::now() - Duration::from_secs(grace_days * 24 * 60 * 60);
+
+    let to_delete: HashSet<&PathBuf> = delete.iter().collect();
+
+    let mut unreferenced: Vec<UnreferencedObject> = Vec::new();
+    for storage_path in storage_paths {
+        // stray files aren't objects, so they're left alone
+        let key = match storage_path.strip_prefix(&storage_dir).ok().and_then(|rel_path| storage::parse_storage_path(rel_path)) {
+            Some(key) => key,
+            None => continue,
+        };
+        let hash = storage::key_hash(&key);
+        if referenced.contains(&hash) {
+            continue
+        }
+
+        let metadata = storage_path.metadata().ok();
+        let modification_time = metadata.as_ref().and_then(|metadata| metadata.modified().ok());
+        let mut object = UnreferencedObject{
+            storage_path: storage_path.clone(),
+            blake3_checksum: hash,
+            size: metadata.map(|metadata| metadata.len()).unwrap_or_default(),
+            modification_time,
+            reclaimable: modification_time.map_or(false, |time| time < cutoff),
+            deleted: false,
+            error_message: None,
+        };
+
+        if object.reclaimable && to_delete.contains(&storage_path) {
+            match fs::remove_file(&storage_path) {
+                Ok(_) => object.deleted = true,
+                Err(e) => object.error_message = Some(format!("could not remove {}: {e}", storage_path.display())),
+            }
+        }
+        unreferenced.push(object);
+    }
+
+    Ok(unreferenced)
+}
+
+fn references_error(message: String) -> BatchError {
+    BatchError{
+        error: BatchErrorType::ReferencesNotRead,
+        error_message: format!("could not read referenced files, {message}")
+    }
+}
@@ -1,5 +1,5 @@
-use std::path::PathBuf;
-use crate::helpers::{config, copy, error::{BatchError, FileError}, file, hash, outcome::Outcome, repo};
+use std::{collections::{BTreeMap, HashSet}, fs, path::PathBuf, sync::Arc};
+use crate::helpers::{batch, cache, config::{self, Materialization, Mtime}, directory::{self, Member}, error::{BatchError, FileError, FileErrorType}, file, hash, outcome::Outcome, progress::{self, Progress}, repo, storage::{self, Location}};
 
 #[derive(Debug)]
 pub struct RetrievedFile {
@@ -8,17 +8,35 @@ pub struct RetrievedFile {
     pub size: u64,
     pub absolute_path: PathBuf,
     pub blake3_checksum: String,
+    // for a directory, the files in it that aren't in the version retrieved, which are left as they are
+    pub untracked: Vec<PathBuf>,
 }
 
-pub fn get(files: &Vec<PathBuf>) -> std::result::Result<Vec<std::result::Result<RetrievedFile, FileError>>, BatchError> {
+// materialization, mtime and workers override the ones in dvs.yaml; progress, if given, is reported to as files
+// are copied and checked, and can cancel the batch
+pub fn get(files: &Vec<PathBuf>, materialization: Option<Materialization>, mtime: Option<Mtime>, workers: Option<usize>, progress: Option<&Arc<Progress>>) -> std::result::Result<Vec<std::result::Result<RetrievedFile, FileError>>, BatchError> {
     // get git root
     let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
 
     // load the config
     let conf = config::read(&git_dir)?;
 
-    // check storage directory exists
-    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+    // how files are put in the workspace
+    let materialization = materialization.unwrap_or(conf.materialization);
+
+    // whether copied files get their recorded modification time
+    let restore_mtime = mtime.unwrap_or(conf.mtime) == Mtime::Restore;
+
+    // files retrieved at once
+    let workers = config::get_workers(workers, &conf.workers)?;
+
+    // set up the storage locations (checks storage directories exist for local storage)
+    let mut locations = storage::locations_from_config(&conf);
+
+    // error if none of them can be reached
+    if locations.iter().all(|location| location.backend.is_err()) {
+        locations.swap_remove(0).backend?;
+    }
 
         
     // collect queued paths
@@ -32,37 +50,38 @@ pub fn get(files: &Vec<PathBuf>) -> std::result::Result<Vec<std::result::Result<
     // check that metadata file exists for all files
     file::check_meta_files_exist(files)?;
     
-    // get each file in queued_paths
-    Ok(files
-        .into_iter()
-        .map(|file| {
-            get_file(&file, &storage_dir, &git_dir)
-        })
-        .collect::<Vec<std::result::Result<RetrievedFile, FileError>>>())
+    // get each file in queued_paths in parallel; results keep the order of files
+    batch::map_files(files, workers, progress, |file| {
+        get_file(&file, &locations, materialization, restore_mtime, &git_dir)
+    })
 }
 
 
 // gets a file from storage
-pub fn get_file(local_path: &PathBuf, storage_dir: &PathBuf, git_dir: &PathBuf) -> std::result::Result<RetrievedFile, FileError> {
+pub fn get_file(local_path: &PathBuf, locations: &Vec<Location>, materialization: Materialization, restore_mtime: bool, git_dir: &PathBuf) -> std::result::Result<RetrievedFile, FileError> {
     // check if metadata file in git repo
     repo::check_file_in_git_repo(&file::metadata_path(local_path), git_dir)?;
 
     // get metadata
     let metadata = file::load(local_path)?;
 
+    // a directory is retrieved file by file
+    if let Some(members) = &metadata.members {
+        return get_directory(local_path, &metadata, members, locations, materialization)
+    }
+
+    // for reporting progress on copies whose size storage doesn't give
+    progress::set_size(metadata.size);
+
     // get local hash 
     let local_hash = hash::get_file_hash(local_path).unwrap_or_default();
     // get hash from metadata
-    let meta_hash = metadata.blake3_checksum;
-
-    // get storage data
-    let storage_path = hash::get_storage_path(storage_dir, &meta_hash);
+    let meta_hash = &metadata.blake3_checksum;
 
     // check if most current file is already present locally
     let outcome = 
-        if !local_path.exists() || meta_hash == String::from("") || local_hash == String::from("") || local_hash != meta_hash {
-            copy::copy(&storage_path, local_path)?;
-            Outcome::Copied
+        if !local_path.exists() || *meta_hash == String::from("") || local_hash == String::from("") || local_hash != *meta_hash {
+            replace(local_path, meta_hash, Some(&metadata), locations, materialization, restore_mtime)?
         }  // if file not present or not current
         else {
             Outcome::Present
@@ -74,10 +93,135 @@ pub fn get_file(local_path: &PathBuf, storage_dir: &PathBuf, git_dir: &PathBuf)
             absolute_path: file::get_absolute_path(local_path)?, // [MAN-GET-002]
             blake3_checksum: hash::get_file_hash(local_path)?,
             outcome,
-            size: file::get_file_size(local_path)?  // [MAN-GET-004]
+            size: file::get_file_size(local_path)?,  // [MAN-GET-004]
+            untracked: Vec::new(),
         }
     ) // [MAN-GET-001]: switch linux users/literally test with two users where one user adds, and another gets
 }
 
+// brings a directory to the version recorded in its metadata: missing and changed files are retrieved and checked
+// like single files; files that aren't in that version are left in place and returned as untracked
+fn get_directory(local_path: &PathBuf, metadata: &file::Metadata, members: &Vec<Member>, locations: &Vec<Location>, materialization: Materialization) -> std::result::Result<RetrievedFile, FileError> {
+    let file_error = |path: &PathBuf, message: String| FileError{
+        relative_path: file::try_to_get_rel_path(path),
+        absolute_path: file::try_to_get_abs_path(path),
+        error: FileErrorType::FileNotCopied,
+        error_message: Some(message),
+        input: path.clone()
+    };
+
+    let local_members = if local_path.is_dir() {directory::get_members(local_path)?} else {Vec::new()};
+    let local_hashes: BTreeMap<String, String> = local_members.into_iter().map(|member| (member.path, member.blake3_checksum)).collect();
+    let recorded_paths: HashSet<&String> = members.iter().map(|member| &member.path).collect();
+
+    let mut changed = false;
+    let mut linked = false;
+    for member in members.iter().filter(|member| local_hashes.get(&member.path) != Some(&member.blake3_checksum)) {
+        let member_path = directory::member_path(local_path, member)?;
+        if let Some(parent) = member_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| file_error(&member_path, format!("could not create {}: {e}", parent.display())))?;
+        }
+        progress::set_size(member.size);
+        // the attributes recorded are the directory's, not its files'
+        match replace(&member_path, &member.blake3_checksum, None, locations, materialization, false)? {
+            Outcome::Linked => linked = true,
+            _ => changed = true,
+        }
+    }
+
+    Ok(RetrievedFile {
+            relative_path: repo::get_relative_path_to_wd(local_path)?,
+            absolute_path: file::get_absolute_path(local_path)?,
+            blake3_checksum: metadata.blake3_checksum.clone(),
+            outcome: if changed {Outcome::Copied} else if linked {Outcome::Linked} else {Outcome::Present},
+            size: metadata.size,
+            untracked: local_hashes
+                .keys()
+                .filter(|path| !recorded_paths.contains(path))
+                .map(|path| file::try_to_get_rel_path(local_path).unwrap_or(local_path.clone()).join(path))
+                .collect(),
+        }
+    )
+}
+
+// retrieves the version with the given hash, setting the current local file aside so it can be put back if the retrieved one is bad
+fn replace(local_path: &PathBuf, meta_hash: &String, metadata: Option<&file::Metadata>, locations: &Vec<Location>, materialization: Materialization, restore_mtime: bool) -> std::result::Result<Outcome, FileError> {
+    let backup_path = set_aside(local_path)?;
+    let result = retrieve(local_path, meta_hash, metadata, locations, materialization, restore_mtime);
+    if let Some(backup_path) = backup_path {
+        if result.is_ok() {
+            let _ = fs::remove_file(&backup_path);
+        }
+        else {
+            // remove a bad copy or link before putting the previous file back
+            let _ = fs::remove_file(local_path);
+            let _ = fs::rename(&backup_path, local_path);
+        }
+    }
+    result
+}
+
+// try each storage location in order, then check the retrieved file against its metadata,
+// whether it was copied or linked; attributes are restored from the metadata, if given
+fn retrieve(local_path: &PathBuf, meta_hash: &String, metadata: Option<&file::Metadata>, locations: &Vec<Location>, materialization: Materialization, restore_mtime: bool) -> std::result::Result<Outcome, FileError> {
+    let (outcome, object_location) = storage::materialize_from_locations(locations, meta_hash, local_path, materialization)?;
+
+    // copies are checked against the hash as they're written, links are the object as it is in storage
+    if outcome == Outcome::Linked {
+        let retrieved_hash = hash::hash_file_with_blake3(local_path).map_err(|e|
+            FileError{
+                relative_path: file::try_to_get_rel_path(local_path),
+                absolute_path: file::try_to_get_abs_path(local_path),
+                error: FileErrorType::HashNotFound,
+                error_message: Some(format!("could not read {} to check it: {e}", local_path.display())),
+                input: local_path.clone()
+            }
+        )?.unwrap_or_default();
+        if retrieved_hash != *meta_hash {
+            let _ = fs::remove_file(local_path);
+            return Err(storage::corrupt_object_error(local_path, &object_location, meta_hash, &retrieved_hash))
+        }
+    }
+
+    // before the hash is cached, since the cache is checked against the modification time
+    if let Some(metadata) = metadata.filter(|_| outcome == Outcome::Copied) {
+        if let Err(e) = file::restore_file_attributes(local_path, metadata, restore_mtime) {
+            let _ = fs::remove_file(local_path);
+            return Err(e)
+        }
+    }
+    let _ = cache::write_hash_to_cache(local_path, meta_hash);
+    Ok(outcome)
+}
+
+// where the local file is kept while a new version is retrieved: .<file name>.dvs-backup beside it. one name per
+// file, so a backup left by a get that was killed part way is found and put back by the next get of the file
+fn backup_path(local_path: &PathBuf) -> PathBuf {
+    let file_name = local_path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    local_path.with_file_name(format!(".{file_name}.dvs-backup"))
+}
+
+// move the local file out of the way of a new version; None if there isn't one
+fn set_aside(local_path: &PathBuf) -> std::result::Result<Option<PathBuf>, FileError> {
+    let backup_path = backup_path(local_path);
+    // the file was set aside by a get that didn't finish; if the file is there too, the backup is stale and replaced
+    if local_path.symlink_metadata().is_err() && backup_path.symlink_metadata().is_ok() {
+        let _ = fs::rename(&backup_path, local_path);
+    }
+    if local_path.symlink_metadata().is_err() {
+        return Ok(None)
+    }
+    fs::rename(local_path, &backup_path).map_err(|e|
+        FileError{
+            relative_path: file::try_to_get_rel_path(local_path),
+            absolute_path: file::try_to_get_abs_path(local_path),
+            error: FileErrorType::FileNotCopied,
+            error_message: Some(format!("could not move {} aside to replace it: {e}", local_path.display())),
+            input: local_path.clone()
+        }
+    )?;
+    Ok(Some(backup_path))
+}
+
 
 
@@ -1,4 +1,4 @@
-use crate::helpers::{config, error::{InitError, InitErrorType}, repo};
+use crate::helpers::{config, error::{InitError, InitErrorType}, layout::{self, Layout}, repo};
 use std::{ffi::OsStr, fs::{self, create_dir}, os::unix::fs::PermissionsExt, path::PathBuf};
 use file_owner::Group;
 use std::env;
@@ -115,7 +115,7 @@ pub fn dvs_init(storage_dir: &PathBuf, octal_permissions: Option<i32>, group_nam
         if !storage_dir_abs.is_dir() {
             return Err(InitError{
                 error: InitErrorType::StorageDirNotADir, 
-                error_message: format!("file path inputted")
+                error_message: String::from("file path inputted")
             });
         }
 
@@ -132,6 +132,17 @@ pub fn dvs_init(storage_dir: &PathBuf, octal_permissions: Option<i32>, group_nam
         }
     } // else, storage directory exists
 
+    // record how objects are laid out, unless the storage directory already does;
+    // directories without a layout file were written with the default one
+    if !storage_dir_abs.join(layout::LAYOUT_FILE).exists() {
+        layout::write(&storage_dir_abs, &Layout::default()).map_err(|e|
+            InitError{
+                error: InitErrorType::LayoutNotWritten,
+                error_message: format!("{} not written in {}. {e}", layout::LAYOUT_FILE, storage_dir.display())
+            }
+        )?;
+    }
+
     // warn if storage directory is in git repo
     if repo::dir_in_git_repo(&storage_dir_abs, &git_dir) {
         println!("warning: the storage directory is located in the git repo directory.\nfiles added to the storage directory will be uploaded directly to git.") // [MAN-INI-003]
@@ -142,7 +153,18 @@ pub fn dvs_init(storage_dir: &PathBuf, octal_permissions: Option<i32>, group_nam
         &config::Config{
             storage_dir: storage_dir_abs.clone(), 
             permissions: permissions,
-            group: group.clone()
+            group: group.clone(),
+            backend: config::Backend::Local,
+            compression: config::Compression::None,
+            compression_level: None,
+            chunking: None,
+            replicas: Vec::new(),
+            materialization: config::Materialization::Copy,
+            workers: None,
+            encryption: None,
+            record_attributes: false,
+            mtime: config::Mtime::Touch,
+            digests: Vec::new(),
         }, 
         &git_dir).map_err(|e|
             InitError{
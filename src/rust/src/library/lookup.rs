@@ -0,0 +1,52 @@
+use crate::helpers::{config, error::{BatchError, BatchErrorType}, index::{self, IndexEntry}, repo};
+use std::path::PathBuf;
+
+// who added the object with the given hash, or a hash starting with it, and from where; and/or
+// which versions the given file in this project was added as, oldest first
+pub fn lookup(hash: Option<&str>, path: Option<&PathBuf>) -> std::result::Result<Vec<IndexEntry>, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // check storage directory exists
+    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+
+    // the index is split by the first 2 characters of the hash
+    let hash = hash.map(|hash| hash.to_lowercase());
+    if let Some(hash) = &hash {
+        if hash.len() < 2 || hash.len() > 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(BatchError{
+                error: BatchErrorType::HashInvalid,
+                error_message: format!("{hash} is not a blake3 hash or the start of one (at least 2 hex characters)")
+            })
+        }
+    }
+
+    // paths are recorded relative to the root of the project they were added in
+    let file = match path {
+        Some(path) => Some((
+            repo::repo_identity(&git_dir),
+            repo::get_relative_path(&git_dir, path).map_err(|e|
+                BatchError{
+                    error: BatchErrorType::AnyFilesDNE,
+                    error_message: format!("could not get path of {} in the project: {e}", path.display())
+                }
+            )?,
+        )),
+        None => None,
+    };
+
+    let entries = index::read(&storage_dir, hash.as_deref()).map_err(|e|
+        BatchError{
+            error: BatchErrorType::IndexNotRead,
+            error_message: format!("could not read the index of {}: {e}", storage_dir.display())
+        }
+    )?;
+
+    Ok(entries
+        .into_iter()
+        .filter(|entry| file.as_ref().map_or(true, |(repo, relative_path)| entry.repo == *repo && entry.relative_path == *relative_path))
+        .collect())
+}
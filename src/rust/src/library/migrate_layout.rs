@@ -0,0 +1,129 @@
+use crate::helpers::{config::{self, Backend}, error::{BatchError, BatchErrorType}, layout, repo, storage};
+use std::{fs, path::PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug)]
+pub struct Migration {
+    pub storage_directory: PathBuf,
+    pub from_sharding_depth: u32,
+    pub to_sharding_depth: u32,
+    pub moved: u64,
+    // files left where they were because they aren't objects in either layout, see dvs_fsck
+    pub skipped: u64,
+}
+
+// moves the objects in the storage directory to another sharding depth in place;
+// if interrupted, running it again with the same depth picks up where it stopped
+pub fn migrate_layout(sharding_depth: u32) -> std::result::Result<Migration, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // only objects on a locally mounted filesystem are moved: s3 storage keeps the default layout,
+    // and a storage directory reached over sftp or http is migrated on the host it's on
+    match &conf.backend {
+        Backend::Local => (),
+        Backend::S3(_) => return Err(migration_error(String::from("s3 storage keeps the default layout and can't be migrated"))),
+        _ => return Err(migration_error(String::from("only a storage directory on a locally mounted filesystem can be migrated; migrate it on the host it's on"))),
+    }
+
+    // check storage directory exists
+    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+
+    layout::check_sharding_depth(sharding_depth)?;
+    let mut current = layout::read(&storage_dir)?;
+    let from = current.sharding_depth;
+
+    let mut migration = Migration{
+        storage_directory: storage_dir.clone(),
+        from_sharding_depth: from,
+        to_sharding_depth: sharding_depth,
+        moved: 0,
+        skipped: 0,
+    };
+
+    match current.migrating_to {
+        // one migration at a time, or objects could end up at three depths
+        Some(depth) if depth != sharding_depth => return Err(migration_error(
+            format!("a migration of {} to sharding depth {depth} was interrupted; finish it first by migrating to {depth} again", storage_dir.display())
+        )),
+        None if from == sharding_depth => return Ok(migration),
+        _ => (),
+    }
+
+    // recorded first, so objects are looked for at both depths while they're moved
+    // and an interrupted migration can be resumed
+    current.migrating_to = Some(sharding_depth);
+    layout::write(&storage_dir, &current).map_err(|e|
+        migration_error(format!("could not write {} in {}: {e}", layout::LAYOUT_FILE, storage_dir.display()))
+    )?;
+
+    let storage_paths = storage::walk_storage_dir(&storage_dir).map_err(|e|
+        BatchError{
+            error: BatchErrorType::StorageDirNotFound,
+            error_message: format!("could not read storage_dir: {}, {e}", storage_dir.display())
+        }
+    )?;
+
+    for storage_path in storage_paths {
+        let key = match storage_path.strip_prefix(&storage_dir).ok().and_then(|rel_path| storage::parse_storage_path(rel_path)) {
+            Some(key) => key,
+            None => {
+                migration.skipped += 1;
+                continue
+            }
+        };
+        let migrated_path = layout::sharded_path(&storage_dir, &key, sharding_depth);
+        // moved before an interruption
+        if storage_path == migrated_path {
+            continue
+        }
+        if storage_path != layout::sharded_path(&storage_dir, &key, from) {
+            migration.skipped += 1;
+            continue
+        }
+
+        // an object added at the new depth during the migration makes the old copy redundant
+        let result =
+            if migrated_path.exists() {
+                fs::remove_file(&storage_path)
+            }
+            else {
+                migrated_path.parent().map_or(Ok(()), |parent| fs::create_dir_all(parent))
+                    .and_then(|_| fs::rename(&storage_path, &migrated_path))
+            };
+        result.map_err(|e|
+            migration_error(format!("could not move {} to {}: {e}; migrate again to resume", storage_path.display(), migrated_path.display()))
+        )?;
+        migration.moved += 1;
+    }
+
+    // remove the shard directories emptied by the move; ones that still hold files are kept
+    let shard_dirs = WalkDir::new(&storage_dir)
+        .min_depth(1)
+        .contents_first(true)
+        .into_iter()
+        .filter_entry(|entry| !entry.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir());
+    for shard_dir in shard_dirs {
+        let _ = fs::remove_dir(shard_dir.path());
+    }
+
+    current.sharding_depth = sharding_depth;
+    current.migrating_to = None;
+    layout::write(&storage_dir, &current).map_err(|e|
+        migration_error(format!("could not write {} in {}: {e}; migrate again to finish", layout::LAYOUT_FILE, storage_dir.display()))
+    )?;
+
+    Ok(migration)
+}
+
+fn migration_error(message: String) -> BatchError {
+    BatchError{
+        error: BatchErrorType::LayoutNotMigrated,
+        error_message: message
+    }
+}
@@ -0,0 +1,86 @@
+use crate::helpers::{error::BatchError, file::{self, Metadata, METADATA_VERSION}, parse, repo};
+use serde_json::{Map, Value};
+use std::{fs, path::PathBuf};
+
+#[derive(Debug)]
+pub struct MigratedFile {
+    // relative to the root of the project
+    pub relative_path: PathBuf,
+    pub from_version: Option<u32>,
+    pub to_version: Option<u32>,
+    // what was changed, empty if the file was already current
+    pub changes: Vec<String>,
+    pub error_message: Option<String>,
+}
+
+// each step upgrades the fields of a .dvs file from the version before it, returning what it changed;
+// MIGRATIONS[n] upgrades version n to n + 1
+type Migration = fn(&mut Map<String, Value>) -> Vec<String>;
+const MIGRATIONS: [Migration; METADATA_VERSION as usize] = [to_version_1];
+
+// version 1 only records the version itself
+fn to_version_1(_fields: &mut Map<String, Value>) -> Vec<String> {
+    Vec::new()
+}
+
+// upgrades every .dvs file in the project to the version this version of dvs writes; fields it doesn't know
+// are kept, and files written by a newer version of dvs are left as they are
+pub fn migrate_metadata() -> std::result::Result<Vec<MigratedFile>, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    let mut paths = parse::get_meta_files_in(&git_dir);
+    paths.sort();
+
+    Ok(paths.iter().map(|path| {
+        let mut migrated = MigratedFile{
+            relative_path: repo::get_relative_path(&git_dir, path).unwrap_or(path.clone()),
+            from_version: None,
+            to_version: None,
+            changes: Vec::new(),
+            error_message: None,
+        };
+        if let Err(e) = migrate_file(path, &mut migrated) {
+            migrated.error_message = Some(e.to_string());
+        }
+        migrated
+    }).collect())
+}
+
+fn migrate_file(path: &PathBuf, migrated: &mut MigratedFile) -> file::Result<()> {
+    let metadata_path = file::metadata_path(path);
+    let contents = fs::read_to_string(&metadata_path)?;
+    let mut fields = match serde_json::from_str::<Value>(&contents)? {
+        Value::Object(fields) => fields,
+        _ => return Err(format!("{} is not a JSON object", metadata_path.display()).into()),
+    };
+
+    let version = match fields.get("metadata_version") {
+        None => 0,
+        Some(version) => version.as_u64().and_then(|version| u32::try_from(version).ok()).ok_or_else(||
+            format!("metadata_version in {} is not a version number: {version}", metadata_path.display())
+        )?,
+    };
+    migrated.from_version = Some(version);
+    migrated.to_version = Some(version);
+    if version >= METADATA_VERSION {
+        return Ok(())
+    }
+
+    let mut changes: Vec<String> = Vec::new();
+    for migration in &MIGRATIONS[version as usize..] {
+        changes.extend(migration(&mut fields));
+    }
+    fields.insert(String::from("metadata_version"), Value::from(METADATA_VERSION));
+    changes.push(format!("metadata_version {version} -> {METADATA_VERSION}"));
+
+    // the upgraded file must still load before it replaces the old one
+    let metadata: Metadata = serde_json::from_value(Value::Object(fields)).map_err(|e|
+        format!("could not read {} as a .dvs file: {e}", metadata_path.display())
+    )?;
+    file::save(&metadata, path)?;
+
+    migrated.to_version = Some(METADATA_VERSION);
+    migrated.changes = changes;
+    Ok(())
+}
@@ -2,4 +2,13 @@ pub mod init;
 pub mod add;
 pub mod get;
 pub mod status;
-pub mod info;
\ No newline at end of file
+pub mod info;
+pub mod serve;
+pub mod fsck;
+pub mod gc;
+pub mod usage;
+pub mod migrate_layout;
+pub mod lookup;
+pub mod migrate_metadata;
+pub mod annotate;
+pub mod verify;
@@ -0,0 +1,166 @@
+use crate::helpers::{config, error::{BatchError, BatchErrorType}, outcome::Outcome, repo, storage::{self, LocalStorage, StorageBackend}};
+use std::{fs::File, io, path::{Path, PathBuf}, sync::{mpsc, Arc}, thread, time::Duration};
+use chrono::{DateTime, Utc};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+// requests are handled on this many threads
+const WORKERS: usize = 4;
+
+// serves the project's storage directory over http:
+// HEAD/GET/PUT /<hash[..2]>/<hash[2..]>, checking uploads against their hash before they're stored and
+// refusing ones larger than max_upload_size bytes. report is called on this thread every so often with
+// what happened since, e.g. the requests handled; the server stops once it returns false
+pub fn serve(address: &str, token: Option<&str>, max_upload_size: u64, mut report: impl FnMut(Vec<String>) -> bool) -> std::result::Result<(), BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // serve storage_dir with the permissions and group from dvs.yaml
+    let storage = Arc::new(storage::local_from_config(&conf)?);
+
+    let server = Arc::new(Server::http(address).map_err(|e|
+        BatchError{
+            error: BatchErrorType::ServerNotStarted,
+            error_message: format!("could not listen on {address}: {e}")
+        }
+    )?);
+
+    let (log, logged) = mpsc::channel::<String>();
+    let _ = log.send(format!("serving {} on http://{address}", storage.storage_dir.display()));
+
+    let token = token.map(String::from);
+    let workers: Vec<thread::JoinHandle<()>> = (0..WORKERS).map(|_| {
+        let server = Arc::clone(&server);
+        let storage = Arc::clone(&storage);
+        let token = token.clone();
+        let log = log.clone();
+        thread::spawn(move || {
+            // until the server is unblocked to stop
+            while let Ok(request) = server.recv() {
+                let _ = log.send(handle(request, &storage, &token, max_upload_size));
+            }
+        })
+    }).collect();
+
+    while report(logged.try_iter().collect()) {
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // each worker stops at its next recv, after the request it's handling
+    for _ in &workers {
+        server.unblock();
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+    let _ = log.send(format!("stopped serving {}", storage.storage_dir.display()));
+    report(logged.try_iter().collect());
+    Ok(())
+}
+
+// handles the request and returns a line for the log
+fn handle(mut request: Request, storage: &LocalStorage, token: &Option<String>, max_upload_size: u64) -> String {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+
+    let response = match parse_key(&url) {
+        None => Response::from_string("not found").with_status_code(404).boxed(),
+        Some(key) => match method {
+            Method::Head | Method::Get => get_object(storage, &key),
+            Method::Put if !authorized(&request, token) => Response::from_string("unauthorized").with_status_code(401).boxed(),
+            Method::Put if request.body_length().map_or(false, |length| length as u64 > max_upload_size) => too_large(max_upload_size),
+            Method::Put => put_object(storage, &key, request.as_reader(), max_upload_size),
+            _ => Response::from_string("method not allowed").with_status_code(405).boxed(),
+        }
+    };
+
+    let line = format!("{method} {url} {}", response.status_code().0);
+    let _ = request.respond(response);
+    line
+}
+
+fn too_large(max_upload_size: u64) -> tiny_http::ResponseBox {
+    Response::from_string(format!("uploads are limited to {max_upload_size} bytes")).with_status_code(413).boxed()
+}
+
+// /<hash[..2]>/<hash[2..]>, laid out like the storage directory
+fn parse_key(url: &str) -> Option<String> {
+    storage::parse_storage_path(Path::new(url.trim_start_matches('/')))
+}
+
+fn authorized(request: &Request, token: &Option<String>) -> bool {
+    match token {
+        None => true,
+        Some(token) => request
+            .headers()
+            .iter()
+            .any(|header| header.field.equiv("Authorization") && header.value.as_str() == format!("Bearer {token}")),
+    }
+}
+
+fn get_object(storage: &LocalStorage, key: &String) -> tiny_http::ResponseBox {
+    let info = match storage.stat(key) {
+        Ok(info) => info,
+        Err(_) => return Response::from_string("not found").with_status_code(404).boxed(),
+    };
+    let file = match File::open(storage.location(key)) {
+        Ok(file) => file,
+        Err(e) => return Response::from_string(e.to_string()).with_status_code(500).boxed(),
+    };
+
+    let mut response = Response::from_file(file);
+    if let Some(modification_time) = info.modification_time {
+        let last_modified = DateTime::<Utc>::from(modification_time).format("%a, %d %b %Y %H:%M:%S GMT").to_string();
+        if let Ok(header) = Header::from_bytes("Last-Modified", last_modified) {
+            response.add_header(header);
+        }
+    }
+    // tiny_http leaves out the body for HEAD
+    response.boxed()
+}
+
+fn put_object(storage: &LocalStorage, key: &String, body: &mut dyn io::Read, max_upload_size: u64) -> tiny_http::ResponseBox {
+    // uploads without a length are cut off once they pass the limit
+    let mut body = LimitedReader{inner: body, remaining: max_upload_size, exceeded: false};
+
+    // content addressed, so an object that's already there is the same one
+    if storage.exists(key).unwrap_or(false) {
+        return match io::copy(&mut body, &mut io::sink()) {
+            Err(_) if body.exceeded => too_large(max_upload_size),
+            _ => Response::from_string("present").with_status_code(200).boxed(),
+        }
+    }
+
+    match storage.put_verified(&mut body, key) {
+        Ok(Outcome::Present) => Response::from_string("present").with_status_code(200).boxed(),
+        // stored without the key, so its contents are checked when it's retrieved
+        Ok(Outcome::Unchecked) => Response::from_string("unchecked").with_status_code(201).boxed(),
+        Ok(_) => Response::from_string("copied").with_status_code(201).boxed(),
+        Err(_) if body.exceeded => too_large(max_upload_size),
+        // the upload didn't match its hash, or couldn't be written; nothing was stored
+        Err(e) => Response::from_string(e.to_string()).with_status_code(400).boxed(),
+    }
+}
+
+// reads at most remaining bytes, then fails
+struct LimitedReader<'a> {
+    inner: &'a mut dyn io::Read,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl io::Read for LimitedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // one byte more than allowed tells a body at the limit from one past it
+        let max = buf.len().min(self.remaining.saturating_add(1).try_into().unwrap_or(usize::MAX));
+        let bytes_read = self.inner.read(&mut buf[..max])?;
+        if bytes_read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::new(io::ErrorKind::Other, "upload larger than the limit"))
+        }
+        self.remaining -= bytes_read as u64;
+        Ok(bytes_read)
+    }
+}
@@ -1,6 +1,6 @@
 
-use crate::helpers::{config, error::{BatchError, FileError, FileErrorType}, file, hash, outcome::Status, repo};
-use std::path::PathBuf;
+use crate::helpers::{annotation::AnnotationFilter, batch, config, directory::{self, MemberStatus}, error::{BatchError, FileError, FileErrorType}, file, hash, outcome::Status, progress::Progress, repo};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
 
 #[derive(PartialEq, Debug)]
 pub struct FileStatus {
@@ -11,62 +11,91 @@ pub struct FileStatus {
     pub saved_by: String,
     pub message: String,
     pub absolute_path: Option<PathBuf>,
-    pub blake3_checksum: String
+    pub blake3_checksum: String,
+    pub tags: Vec<String>,
+    pub annotations: BTreeMap<String, String>,
+    // for a directory tracked as a unit, the status of each of its files; empty for a file
+    pub members: Vec<MemberStatus>,
 }
 
-pub fn status(files: &Vec<String>) -> std::result::Result<Vec<std::result::Result<FileStatus, FileError>>, BatchError> {
+// only files matching the filter are reported, though files whose metadata can't be read are always reported as errors;
+// workers overrides the ones in dvs.yaml; progress, if given, is reported to as files are hashed, and can cancel the batch
+pub fn status(files: &Vec<String>, filter: &AnnotationFilter, workers: Option<usize>, progress: Option<&Arc<Progress>>) -> std::result::Result<Vec<std::result::Result<FileStatus, FileError>>, BatchError> {
     // Get git root
     let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
 
     // load the config
-    config::read(&git_dir)?;
+    let conf = config::read(&git_dir)?;
+
+    // files checked at once
+    let workers = config::get_workers(workers, &conf.workers)?;
     
     // parsing before entering fxn now
     //let meta_paths: Vec<PathBuf> = parse::parse_files_from_globs_status(globs)?;
 
-    // get the status of each file and collect
-    Ok(files.into_iter().map(|path| {
-        status_file(&PathBuf::from(path))
-    }).collect::<Vec<std::result::Result<FileStatus, FileError>>>())
-} 
+    // get the status of each file in parallel; results keep the order of files
+    let statuses = batch::map_files(files, workers, progress, |path| {
+        status_file(&PathBuf::from(path), filter)
+    })?;
+
+    Ok(statuses.into_iter().filter_map(|status| status.transpose()).collect())
+}
 
-fn status_file(local_path: &PathBuf) -> std::result::Result<FileStatus, FileError> {
+fn status_file(local_path: &PathBuf, filter: &AnnotationFilter) -> std::result::Result<Option<FileStatus>, FileError> {
     // info function, so just try to get abs path
     let absolute_path = file::try_to_get_abs_path(local_path);
 
     // info fn, so just try to get rel path
     let relative_path = file::try_to_get_rel_path(local_path);
 
-    file::check_if_dir(local_path)?;
-
     // check if metadata file exists
     if !file::metadata_path(local_path).exists() {
         return Err(FileError{
             relative_path,
             absolute_path,
             error: FileErrorType::FileNotAdded,
-            error_message: Some(format!("metadata file not found - add the file to dvs to get status")),
+            error_message: Some(String::from("metadata file not found - add the file to dvs to get status")),
             input: local_path.clone()
         })
     }
     
     // load metadata
     let metadata = file::load(local_path)?;
-            
-    // assign status
-    let status = 
-        if !local_path.exists() {
-            Status::Absent
+
+    // skip hashing files that aren't reported
+    if !filter.matches(&metadata) {
+        return Ok(None)
+    }
+
+    // assign status; a directory is current if its files have the tree hash recorded
+    let (status, members) = match &metadata.members {
+        Some(recorded) => {
+            let local = if local_path.is_dir() {directory::get_members(local_path)?} else {Vec::new()};
+            let status =
+                if !local_path.exists() {Status::Absent}
+                else if directory::tree_hash(&local) == metadata.blake3_checksum {Status::Current}
+                else {Status::Unsynced};
+            (status, directory::member_statuses(recorded, &local))
         }
-        else {
-            let current_hash = hash::get_file_hash(local_path)?;
+        None => {
+            file::check_if_dir(local_path)?;
 
-            if current_hash == metadata.blake3_checksum {Status::Current}
-            else {Status::Unsynced}
-        };
+            let status =
+                if !local_path.exists() {
+                    Status::Absent
+                }
+                else {
+                    let current_hash = hash::get_file_hash(local_path)?;
+
+                    if current_hash == metadata.blake3_checksum {Status::Current}
+                    else {Status::Unsynced}
+                };
+            (status, Vec::new())
+        }
+    };
 
     // assemble info into FileStatus
-    Ok(FileStatus{
+    Ok(Some(FileStatus{
             relative_path,
             absolute_path,
             status,
@@ -74,7 +103,10 @@ fn status_file(local_path: &PathBuf) -> std::result::Result<FileStatus, FileErro
             blake3_checksum: metadata.blake3_checksum,
             add_time: metadata.add_time,
             saved_by: metadata.saved_by,
-            message: metadata.message
-        })
+            message: metadata.message,
+            tags: metadata.tags,
+            annotations: metadata.annotations,
+            members,
+        }))
 }
 
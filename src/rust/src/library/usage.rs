@@ -0,0 +1,152 @@
+use crate::helpers::{chunk::{self, Manifest}, compress, config, encrypt, error::{BatchError, BatchErrorType}, file, layout::{self, Layout}, parse, repo, storage};
+use std::{collections::{BTreeMap, HashMap, HashSet}, fs, path::PathBuf};
+
+#[derive(Debug)]
+pub struct Usage {
+    // project, user, month, or total, and unreferenced for the objects none of the files use
+    pub grouping: String,
+    pub group: String,
+    pub files: u64,
+    // distinct versions, so a file added to two projects counts once
+    pub objects: u64,
+    pub size: u64,
+    // bytes the objects take up in the storage directory, after compression and with shared chunks counted once
+    pub storage_size: u64,
+    // objects not in the storage directory
+    pub missing: u64,
+}
+
+#[derive(Default)]
+struct Totals {
+    files: u64,
+    hashes: HashSet<String>,
+}
+
+// what the files added to the given repositories take up in the storage directory,
+// totalled per project, per user who added them, and per month they were added in,
+// and what the objects none of them use take up
+pub fn usage(repos: &Vec<PathBuf>) -> std::result::Result<Vec<Usage>, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // check storage directory exists
+    let storage_dir = config::get_storage_dir(&conf.storage_dir)?;
+
+    // get layout
+    let layout = layout::read(&storage_dir)?;
+
+    // the current project if no repositories are given
+    let repo_dirs = if repos.is_empty() {
+        vec![git_dir]
+    }
+    else {
+        repos.iter().map(|repo| repo::get_nearest_repo_dir(repo)).collect::<std::result::Result<Vec<PathBuf>, BatchError>>()?
+    };
+
+    let mut groups: BTreeMap<(usize, String), Totals> = BTreeMap::new();
+    let mut sizes: HashMap<String, u64> = HashMap::new();
+    let mut projects: HashSet<String> = HashSet::new();
+    for repo_dir in &repo_dirs {
+        // its remote, so clones of a project count once and different projects of the same name don't share a row
+        let project = repo::repo_identity(repo_dir);
+        if !projects.insert(project.clone()) {
+            continue
+        }
+        for path in parse::get_meta_files_in(repo_dir) {
+            let metadata = file::load(&path).map_err(|e|
+                BatchError{
+                    error: BatchErrorType::ReferencesNotRead,
+                    error_message: format!("could not read referenced files, {}: {e}", file::metadata_path(&path).display())
+                }
+            )?;
+            // add_time is RFC 3339, so its first 7 characters are the year and month
+            let month = metadata.add_time.chars().take(7).collect::<String>();
+            for key in [(0, String::from("all")), (1, project.clone()), (2, metadata.saved_by.clone()), (3, month)] {
+                let totals = groups.entry(key).or_default();
+                totals.files += 1;
+                totals.hashes.extend(metadata.objects().into_iter().map(|(hash, _)| hash));
+            }
+            // a directory is stored as the objects of its files
+            sizes.extend(metadata.objects());
+        }
+    }
+
+    // the storage objects of each version with their sizes
+    let stored = sizes.keys().map(|hash| (hash.clone(), stored_objects(&storage_dir, &layout, hash))).collect::<HashMap<String, Vec<(String, u64)>>>();
+
+    // everything else in the storage directory, e.g. versions only other projects use or that gc would remove
+    let referenced: HashSet<&String> = stored.values().flatten().map(|(key, _)| key).collect();
+    let storage_paths = storage::walk_storage_dir(&storage_dir).map_err(|e|
+        BatchError{
+            error: BatchErrorType::StorageDirNotFound,
+            error_message: format!("could not read storage_dir: {}, {e}", storage_dir.display())
+        }
+    )?;
+    let unreferenced: Vec<u64> = storage_paths
+        .iter()
+        .filter(|storage_path| storage_path
+            .strip_prefix(&storage_dir)
+            .ok()
+            .and_then(storage::parse_storage_path)
+            .map_or(false, |key| !referenced.contains(&key)))
+        .filter_map(|storage_path| storage_path.metadata().ok().map(|metadata| metadata.len()))
+        .collect();
+
+    let mut totals: Vec<Usage> = groups.into_iter().map(|((grouping, group), totals)| {
+        let mut keys: HashMap<&String, u64> = HashMap::new();
+        let mut missing = 0;
+        for hash in &totals.hashes {
+            if stored[hash].is_empty() {
+                missing += 1;
+            }
+            keys.extend(stored[hash].iter().map(|(key, size)| (key, *size)));
+        }
+        Usage{
+            grouping: String::from(["total", "project", "user", "month"][grouping]),
+            group,
+            files: totals.files,
+            objects: totals.hashes.len() as u64,
+            size: totals.hashes.iter().map(|hash| sizes[hash]).sum(),
+            storage_size: keys.values().sum(),
+            missing,
+        }
+    }).collect();
+    // stored objects can't be sized as versions without reading them, so both sizes are their size in storage
+    totals.push(Usage{
+        grouping: String::from("unreferenced"),
+        group: storage_dir.display().to_string(),
+        files: 0,
+        objects: unreferenced.len() as u64,
+        size: unreferenced.iter().sum(),
+        storage_size: unreferenced.iter().sum(),
+        missing: 0,
+    });
+    Ok(totals)
+}
+
+// storage keys holding the file with the given hash, with their sizes: the object itself,
+// compressed, encrypted or not, or a manifest and its chunks
+fn stored_objects(storage_dir: &PathBuf, layout: &Layout, hash: &String) -> Vec<(String, u64)> {
+    let stored_size = |key: &String| layout.storage_path(storage_dir, key).metadata().ok().map(|metadata| metadata.len());
+    let mut objects: Vec<(String, u64)> = Vec::new();
+    for key in [hash.clone(), compress::zstd_key(hash), encrypt::encrypted_key(hash)] {
+        if let Some(size) = stored_size(&key) {
+            objects.push((key, size));
+        }
+    }
+
+    let manifest_key = chunk::manifest_key(hash);
+    if let Some(size) = stored_size(&manifest_key) {
+        let manifest = fs::read_to_string(layout.storage_path(storage_dir, &manifest_key)).ok()
+            .and_then(|contents| serde_json::from_str::<Manifest>(&contents).ok());
+        // a file that fits in one chunk is stored as itself, which was counted above
+        for chunk in manifest.map(|manifest| manifest.chunks).unwrap_or_default().iter().filter(|chunk| chunk.blake3_checksum != *hash) {
+            objects.extend(stored_objects(storage_dir, layout, &chunk.blake3_checksum));
+        }
+        objects.push((manifest_key, size));
+    }
+    objects
+}
@@ -0,0 +1,113 @@
+use crate::helpers::{batch, config, error::{BatchError, FileError, FileErrorType}, file, hash::{self, DigestAlgorithm}, outcome::Verification, progress::Progress, repo, storage::{self, Location}};
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+#[derive(Debug)]
+pub struct VerifiedDigest {
+    pub relative_path: Option<PathBuf>,
+    pub absolute_path: Option<PathBuf>,
+    // "local" for the file in the project, or the storage location its object was read from
+    pub source: String,
+    pub algorithm: String,
+    // as recorded in the .dvs file
+    pub expected: String,
+    pub actual: String,
+    pub outcome: Verification,
+}
+
+// checks the local files, or with from_storage their objects in storage, against the digests recorded in their
+// .dvs files: the blake3 hash and any others recorded with digests in dvs.yaml, or only the given algorithms;
+// workers overrides the ones in dvs.yaml; progress, if given, is reported to as files are read, and can cancel the batch
+pub fn verify(files: &Vec<String>, algorithms: &Vec<DigestAlgorithm>, from_storage: bool, workers: Option<usize>, progress: Option<&Arc<Progress>>) -> std::result::Result<Vec<std::result::Result<Vec<VerifiedDigest>, FileError>>, BatchError> {
+    // Get git root
+    let git_dir = repo::get_nearest_repo_dir(&PathBuf::from("."))?;
+
+    // load the config
+    let conf = config::read(&git_dir)?;
+
+    // files checked at once
+    let workers = config::get_workers(workers, &conf.workers)?;
+
+    // set up the storage locations, tried in order for each object
+    let locations = if from_storage {storage::locations_from_config(&conf)} else {Vec::new()};
+
+    batch::map_files(files, workers, progress, |path| {
+        verify_file(&PathBuf::from(path), algorithms, &locations)
+    })
+}
+
+fn verify_file(local_path: &PathBuf, algorithms: &Vec<DigestAlgorithm>, locations: &Vec<Location>) -> std::result::Result<Vec<VerifiedDigest>, FileError> {
+    let relative_path = file::try_to_get_rel_path(local_path);
+    let absolute_path = file::try_to_get_abs_path(local_path);
+    let file_error = |error: FileErrorType, message: String| FileError{
+        relative_path: relative_path.clone(),
+        absolute_path: absolute_path.clone(),
+        error,
+        error_message: Some(message),
+        input: local_path.clone()
+    };
+
+    // check if metadata file exists
+    if !file::metadata_path(local_path).exists() {
+        return Err(file_error(FileErrorType::FileNotAdded, String::from("metadata file not found - add the file to dvs to verify it")))
+    }
+    let metadata = file::load(local_path)?;
+    if metadata.members.is_some() {
+        return Err(file_error(FileErrorType::PathIsDirectory, String::from("directories tracked as a unit aren't verified - the status of their files gives whether each has the hash recorded")))
+    }
+    file::check_if_dir(local_path)?;
+
+    // the digests to check against, by algorithm
+    let mut recorded: BTreeMap<DigestAlgorithm, String> = BTreeMap::new();
+    recorded.insert(DigestAlgorithm::Blake3, metadata.blake3_checksum.clone());
+    for (name, digest) in &metadata.digests {
+        // digests from a newer version of dvs that this one can't compute are skipped
+        if let Ok(algorithm) = config::parse_digest_algorithm(name) {
+            recorded.insert(algorithm, digest.to_lowercase());
+        }
+    }
+    if !algorithms.is_empty() {
+        if let Some(algorithm) = algorithms.iter().find(|algorithm| !recorded.contains_key(algorithm)) {
+            return Err(file_error(FileErrorType::DigestNotRecorded, format!("no {} digest in the .dvs file; add digests to dvs.yaml and add the file again", algorithm.algorithm_to_string())))
+        }
+        recorded.retain(|algorithm, _| algorithms.contains(algorithm));
+    }
+    let to_compute: Vec<DigestAlgorithm> = recorded.keys().copied().collect();
+
+    // the object is read straight out of storage, decoded and reassembled whatever its format
+    let (source, actual) =
+        if locations.is_empty() {
+            (String::from("local"), hash::get_file_digests(local_path, &to_compute)?)
+        }
+        else {
+            // the blake3 hash is always computed, so an object that doesn't match its name is reported as corrupt
+            let mut with_blake3 = to_compute.clone();
+            if !with_blake3.contains(&DigestAlgorithm::Blake3) {
+                with_blake3.push(DigestAlgorithm::Blake3);
+            }
+            let (reader, location) = storage::open_from_locations(locations, &metadata.blake3_checksum, local_path)?;
+            let actual = hash::digest_reader(reader, Some(metadata.size), &with_blake3).map_err(|e|
+                file_error(FileErrorType::HashNotFound, format!("could not read {location}: {e}"))
+            )?;
+            let object_hash = actual.get(&DigestAlgorithm::Blake3.algorithm_to_string()).cloned().unwrap_or_default();
+            if object_hash != metadata.blake3_checksum {
+                return Err(storage::corrupt_object_error(local_path, &location, &metadata.blake3_checksum, &object_hash))
+            }
+            (location, actual)
+        };
+
+    Ok(recorded
+        .into_iter()
+        .map(|(algorithm, expected)| {
+            let actual = actual.get(&algorithm.algorithm_to_string()).cloned().unwrap_or_default();
+            VerifiedDigest{
+                relative_path: relative_path.clone(),
+                absolute_path: absolute_path.clone(),
+                source: source.clone(),
+                algorithm: algorithm.algorithm_to_string(),
+                outcome: if actual == expected {Verification::Match} else {Verification::Mismatch},
+                expected,
+                actual,
+            }
+        })
+        .collect())
+}